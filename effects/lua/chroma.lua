@@ -0,0 +1,21 @@
+require("libs.framework")
+require("libs.colors")
+
+SettingsSchema = {}
+
+-- Colors the strip by musical note, mapping C..B onto the hue wheel
+function Tick()
+	local chroma = Fft_Result:get_chroma()
+	local pitch = Fft_Result:get_dominant_pitch()
+	for i = 1, #Colors do
+		local pitch_class = math.floor((i - 1) * 12 / #Colors)
+		local r, g, b = HsvToRgb(pitch_class / 12, 1, 1)
+		local value = chroma[pitch_class + 1]
+		if pitch ~= pitch_class then
+			value = value / 4
+		end
+		Colors[i].r = r * value
+		Colors[i].g = g * value
+		Colors[i].b = b * value
+	end
+end
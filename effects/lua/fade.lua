@@ -1,5 +1,3 @@
-require("libs.framework")
-
 SettingsSchema = {
 	title = "TurboSettings",
 	type = "object",
@@ -10,22 +8,24 @@ SettingsSchema = {
 	properties = {
 		enable_beep_boops = {
 			type = "boolean",
+			default = false,
 		},
 		intensity = {
 			type = "integer",
 			format = "int32",
 			maximum = 10.0,
 			minimum = 0.0,
+			default = 5,
 		},
 	},
 }
 
--- Local state
-local tickCount = 0
+-- Steps of red the fade moves by per second
+local speed = 60
 
-function Tick()
-	for index = 1, #Colors do
-		Colors[index].r = (tickCount + index) % 256
+function Tick(dt, t)
+	local offset = math.floor(t * speed)
+	for index = 1, #Leds do
+		Leds:set(index, (offset + index) % 256, 0, 0)
 	end
-	tickCount = (tickCount + 1) % 256
 end
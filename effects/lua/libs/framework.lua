@@ -1,14 +1,11 @@
+-- Optional helpers for effects that would rather fill a table of colors than call `Leds` directly
+
 Colors = {}
 
 function Set_colors()
-	local data = {}
-	for i, value in pairs(Colors) do
-		local index = ((i - 1) * 3)
-		data[index + 1] = string.char(value.r)
-		data[index + 2] = string.char(value.g)
-		data[index + 3] = string.char(value.b)
+	for index, value in ipairs(Colors) do
+		Leds:set(index, value.r, value.g, value.b)
 	end
-	Colors_bin = table.concat(data)
 end
 
 function Resize_Colors(len)
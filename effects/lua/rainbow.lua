@@ -3,16 +3,16 @@ require("libs.colors")
 
 SettingsSchema = {}
 
-local tick = 0
+-- Leds the rainbow moves by per second
+local speed = 60
 
-function Tick()
+function Tick(dt, t)
+	local offset = t * speed
 	for index = 0, #Colors - 1 do
-		local hue = (index + tick) % #Colors / #Colors
+		local hue = (index + offset) % #Colors / #Colors
 		local r, g, b = HsvToRgb(hue, 1, 1)
 		Colors[index + 1].r = r
 		Colors[index + 1].g = g
 		Colors[index + 1].b = b
 	end
-
-	tick = tick + 1
 end
@@ -10,12 +10,14 @@ SettingsSchema = {
 	properties = {
 		enable_beep_boops = {
 			type = "boolean",
+			default = false,
 		},
 		intensity = {
 			type = "integer",
 			format = "int32",
 			maximum = 10.0,
 			minimum = 0.0,
+			default = 5,
 		},
 	},
 }
@@ -23,9 +25,9 @@ SettingsSchema = {
 local whole_strip = false
 local speed = 2
 function Tick()
-	local new_r = math.floor(math.min(4 * Fft_Result:get_average_amplitude(0, 150), 255))
-	local new_g = math.floor(math.min(15 * Fft_Result:get_average_amplitude(100, 1100), 255))
-	local new_b = math.floor(math.min(20 * Fft_Result:get_average_amplitude(1000, 2000), 255))
+	local new_r = math.floor(255 * Fft_Result:get_average_amplitude(0, 150, "mid", "normalized"))
+	local new_g = math.floor(255 * Fft_Result:get_average_amplitude(100, 1100, "mid", "normalized"))
+	local new_b = math.floor(255 * Fft_Result:get_average_amplitude(1000, 2000, "mid", "normalized"))
 	if whole_strip then
 		for i = 1, #Colors do
 			Colors[i].r = new_r
@@ -3,15 +3,15 @@ require("libs.colors")
 
 SettingsSchema = {}
 
-local view = 800
-local tick = 0
+-- Leds the colors move by per second
+local speed = 60
 
-function Tick()
-	tick = tick + 1
+function Tick(dt, t)
+	local offset = t * speed
+	local bands = Fft_Result:get_bands(#Colors, "log", "mid", "normalized")
 	for i = 0, #Colors - 1 do
-		local step = view / #Colors
-		local value = math.min(Fft_Result:get_frequency_amplitude(i * step) * 5, 255)
-		local hue = (i + tick) % #Colors / #Colors
+		local value = 255 * bands[i + 1]
+		local hue = (i + offset) % #Colors / #Colors
 		local r, g, b = HsvToRgb(hue, 1, 1)
 		Colors[i + 1].r = r / 255 * value
 		Colors[i + 1].g = g / 255 * value
@@ -2,13 +2,11 @@ require("libs.framework")
 
 SettingsSchema = {}
 
--- Local state
-local tip_position = 0
-
 function Tick()
-    local new_r = math.floor(math.min(4 * Fft_Result:get_average_amplitude(0, 150), 255))
-    local new_g = math.floor(math.min(15 * Fft_Result:get_average_amplitude(100, 1100), 255))
-    local new_b = math.floor(math.min(20 * Fft_Result:get_average_amplitude(1000, 2000), 255))
+    local new_r = math.floor(255 * Fft_Result:get_average_amplitude(0, 150, "mid", "normalized"))
+    local new_g = math.floor(255 * Fft_Result:get_average_amplitude(100, 1100, "mid", "normalized"))
+    local new_b = math.floor(255 * Fft_Result:get_average_amplitude(1000, 2000, "mid", "normalized"))
+    local peak = Fft_Result:get_average_amplitude(0, 150, "mid", "peak_hold")
 
     local red_bar_length = math.floor(#Colors * (new_r / 255.0))
     for index = 1, red_bar_length do
@@ -23,15 +21,9 @@ function Tick()
         Colors[index].b = 0
     end
 
-
+    local tip_position = math.floor(#Colors * peak)
     local tip_length = math.floor(5 * (tip_position / #Colors)) + 2
-    if tip_position < red_bar_length then
-        tip_position = math.min(#Colors - tip_length, red_bar_length + 1)
-    else
-        local speed = 2
-        tip_position = math.max(0, tip_position - speed)
-    end
-
+    tip_position = math.min(#Colors - tip_length, tip_position + 1)
 
     if tip_position > 1 then
         for index = 0, tip_length do
@@ -1,6 +1,9 @@
 use rand::Rng;
 use std::sync::Mutex;
-use turbo_plugin::{make_native_effect_plugin, effect_plugin::NativeEffectPlugin, Color};
+use turbo_plugin::{
+    effect_plugin::{FrameTime, NativeEffectPlugin},
+    make_native_effect_plugin, Color,
+};
 
 #[derive(Clone, Copy, Debug)]
 pub struct RaindropSettings {
@@ -39,7 +42,7 @@ impl NativeEffectPlugin for Raindrop {
         CSTR_NAME.as_ptr()
     }
 
-    fn tick(&self, leds: &mut [Color]) {
+    fn tick(&self, leds: &mut [Color], _time: FrameTime) {
         let mut state = self.state.lock().unwrap();
         leds.fill(Color { r: 0, g: 0, b: 0 });
         let color_size = leds.len();
@@ -0,0 +1,121 @@
+use crate::{
+    audio::{
+        audio_processing::{AnalysisResults, AudioSignalProcessor},
+        audio_source::{AudioSource, AudioSourceConfig},
+        audio_stream::InputConfig,
+    },
+    config_parser::TurboAudioConfig,
+};
+use anyhow::{bail, Context, Result};
+use std::collections::HashMap;
+
+/// An audio source and the processor analyzing it.
+pub struct AudioInput {
+    pub source: AudioSource,
+    pub processor: AudioSignalProcessor,
+}
+
+impl AudioInput {
+    /// Starts the source and sets up its processor with the analysis settings of `config`.
+    pub fn start(
+        source_config: &AudioSourceConfig,
+        device_name: Option<String>,
+        sample_rate: u32,
+        input_config: InputConfig,
+        config: &TurboAudioConfig,
+    ) -> Result<Self> {
+        let (source, audio_rx, channel_count, sample_rate) =
+            AudioSource::start(source_config, device_name, sample_rate, input_config)?;
+        let mut processor = AudioSignalProcessor::new(
+            audio_rx,
+            sample_rate,
+            channel_count,
+            config.fft,
+            config.agc,
+            config.smoothing.clone(),
+        );
+        processor.set_fixed_bpm(config.fixed_bpm);
+        Ok(Self { source, processor })
+    }
+}
+
+/// The main audio input, described by the top level fields of the settings, and the named ones
+/// listed in `audio_inputs`.
+pub struct AudioInputs {
+    pub main: AudioInput,
+    pub named: Vec<(String, AudioInput)>,
+}
+
+impl AudioInputs {
+    pub fn start(config: &TurboAudioConfig) -> Result<Self> {
+        let main = AudioInput::start(
+            &config.audio_source,
+            config.device_name.clone(),
+            config.sample_rate,
+            config.input,
+            config,
+        )
+        .context("Couldn't start the main audio input")?;
+
+        let mut named: Vec<(String, AudioInput)> = vec![];
+        for input_config in config.audio_inputs.iter() {
+            if named.iter().any(|(name, _)| *name == input_config.name) {
+                bail!("There are several audio inputs named {}", input_config.name);
+            }
+            log::info!("Starting the audio input {}", input_config.name);
+            let input = AudioInput::start(
+                &input_config.audio_source,
+                input_config.device_name.clone(),
+                input_config.sample_rate,
+                input_config.input,
+                config,
+            )
+            .with_context(|| format!("Couldn't start the audio input {}", input_config.name))?;
+            named.push((input_config.name.clone(), input));
+        }
+        Ok(Self { main, named })
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &AudioInput> {
+        std::iter::once(&self.main).chain(self.named.iter().map(|(_, input)| input))
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut AudioInput> {
+        std::iter::once(&mut self.main).chain(self.named.iter_mut().map(|(_, input)| input))
+    }
+
+    pub fn results(&self) -> AudioInputResults {
+        AudioInputResults {
+            main: self.main.processor.results(),
+            named: self
+                .named
+                .iter()
+                .map(|(name, input)| (name.clone(), input.processor.results()))
+                .collect(),
+        }
+    }
+}
+
+/// The results of every audio input, looked up by the effects.
+pub struct AudioInputResults {
+    main: AnalysisResults,
+    named: HashMap<String, AnalysisResults>,
+}
+
+impl AudioInputResults {
+    /// Results of a single input, also used in place of every named input.
+    pub fn single(main: AnalysisResults, names: impl IntoIterator<Item = String>) -> Self {
+        Self {
+            named: names.into_iter().map(|name| (name, main.clone())).collect(),
+            main,
+        }
+    }
+
+    /// The results of the input named `name`, or of the main input when `name` is `None`.
+    pub fn get(&self, name: Option<&str>) -> Option<&AnalysisResults> {
+        match name {
+            Some(name) => self.named.get(name),
+            None => Some(&self.main),
+        }
+    }
+}
@@ -1,13 +1,87 @@
+use crate::audio::{
+    beat_detection::{BeatDetector, BeatResult},
+    chroma::{dominant_pitch, Chroma, ChromaExtractor, KeyTracker},
+    gain_control::{AgcConfig, AutomaticGainControl},
+    sample_buffer::SampleConsumer,
+    signal_features::SignalFeatures,
+    spectrum_smoothing::{SmoothingConfig, SpectrumSmoothing},
+    tempo_tracking::TempoTracker,
+};
 use dasp::Sample;
 use dasp_signal::Signal;
-use dasp_window::Window;
 use rustfft::num_complex::Complex;
-use std::sync::{Arc, RwLock};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, Instant},
+};
+use turbo_plugin::audio_api::{AudioChannel, BandScale, KeyMode, PitchClass, SpectrumView};
+
+// Floor of the decibel scale, used for silent bins
+const MIN_DECIBELS: f32 = -120.0;
+// The audio callback doesn't deliver samples on every tick. The input is only considered silent
+// once no samples arrived for this long
+const MAX_SAMPLE_GAP: Duration = Duration::from_millis(100);
+// Shortest time between two reports of samples lost by the buffer
+const BUFFER_STATS_LOG_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Unit of the amplitudes returned by `FftResult`.
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub enum SpectrumScale {
+    /// Linear amplitude. A full scale sine wave has an amplitude of 1.
+    #[default]
+    Magnitude,
+    /// Squared magnitude.
+    Power,
+    /// Magnitude in dBFS, from `MIN_DECIBELS` to 0.
+    Decibels,
+}
+
+impl SpectrumScale {
+    fn convert_magnitude(&self, magnitude: f32) -> f32 {
+        match self {
+            SpectrumScale::Magnitude => magnitude,
+            SpectrumScale::Power => magnitude * magnitude,
+            SpectrumScale::Decibels => (20.0 * magnitude.log10()).max(MIN_DECIBELS),
+        }
+    }
+}
+
+// Converts frequencies to and from the unit in which the bands of a `BandScale` are equally spaced
+fn band_scale_from_frequency(scale: BandScale, frequency: f32) -> f32 {
+    match scale {
+        BandScale::Linear => frequency,
+        BandScale::Log => frequency.max(f32::EPSILON).ln(),
+        BandScale::Mel => 2595.0 * (1.0 + frequency / 700.0).log10(),
+        BandScale::Bark => 26.81 * frequency / (1960.0 + frequency) - 0.53,
+    }
+}
+
+fn band_scale_to_frequency(scale: BandScale, value: f32) -> f32 {
+    match scale {
+        BandScale::Linear => value,
+        BandScale::Log => value.exp(),
+        BandScale::Mel => 700.0 * (10.0f32.powf(value / 2595.0) - 1.0),
+        BandScale::Bark => 1960.0 * (value + 0.53) / (26.28 - value),
+    }
+}
+
+type BandsKey = (usize, BandScale, AudioChannel, SpectrumView);
 
 #[derive(Default)]
 pub struct FftResult {
-    raw_bins: Vec<f32>,
+    // One spectrum per `SpectrumView` and `AudioChannel`, indexed by `[view as usize][channel as
+    // usize]`. Only holds the bins from 0 to the Nyquist frequency, as linear magnitudes.
+    bins: [[Vec<f32>; AudioChannel::COUNT]; SpectrumView::COUNT],
     fft_resolution: f32,
+    scale: SpectrumScale,
+    band_frequency_range: (f32, f32),
+    // Bands that were requested since the last FFT. Computed on first use, then shared by every
+    // effect asking for the same bands
+    bands_cache: Mutex<HashMap<BandsKey, Vec<f32>>>,
+    chroma: [Chroma; AudioChannel::COUNT],
+    key: Option<(PitchClass, KeyMode)>,
 }
 
 impl Drop for FftResult {
@@ -17,36 +91,148 @@ impl Drop for FftResult {
 }
 
 impl FftResult {
-    pub fn new(raw_bins: Vec<f32>, fft_resolution: f32) -> Self {
+    pub fn new(sample_rate: u32, fft_config: &FftConfig) -> Self {
+        let fft_resolution = sample_rate as f32 / fft_config.size as f32;
+        let max_frequency = (fft_config.size / 2) as f32 * fft_resolution;
         Self {
-            raw_bins,
+            bins: std::array::from_fn(|_| {
+                std::array::from_fn(|_| vec![0.0f32; fft_config.size / 2 + 1])
+            }),
             fft_resolution,
+            scale: fft_config.scale,
+            band_frequency_range: (
+                fft_config.band_min_frequency.max(fft_resolution),
+                fft_config.band_max_frequency.min(max_frequency),
+            ),
+            bands_cache: Default::default(),
+            chroma: Default::default(),
+            key: None,
         }
     }
 
     pub fn get_max_frequency(&self) -> f32 {
-        self.get_bin_frequency_at_index(self.raw_bins.len() - 1)
+        self.get_bin_frequency_at_index(self.bins[0][0].len() - 1)
+    }
+
+    pub fn get_frequency_amplitude(
+        &self,
+        frequency: f32,
+        channel: AudioChannel,
+        view: SpectrumView,
+    ) -> Option<f32> {
+        Some(self.convert_magnitude(
+            self.get_frequency_magnitude(frequency, self.get_bins(channel, view))?,
+            view,
+        ))
+    }
+
+    /// The average is computed on the magnitudes, then converted to the configured scale.
+    pub fn get_average_amplitude(
+        &self,
+        lower_frequency: f32,
+        upper_frequency: f32,
+        channel: AudioChannel,
+        view: SpectrumView,
+    ) -> Option<f32> {
+        Some(self.convert_magnitude(
+            self.get_area_under_curve(
+                lower_frequency,
+                upper_frequency,
+                self.get_bins(channel, view),
+            )? / (upper_frequency - lower_frequency),
+            view,
+        ))
+    }
+
+    /// Calls `f` with the average amplitudes of `band_count` bands, spread between the configured
+    /// band frequencies according to `scale`.
+    pub fn with_bands<R>(
+        &self,
+        band_count: usize,
+        scale: BandScale,
+        channel: AudioChannel,
+        view: SpectrumView,
+        f: impl FnOnce(&[f32]) -> R,
+    ) -> R {
+        let mut bands_cache = self.bands_cache.lock().unwrap();
+        let bands = bands_cache
+            .entry((band_count, scale, channel, view))
+            .or_insert_with(|| self.compute_bands(band_count, scale, channel, view));
+        f(bands)
+    }
+
+    /// Linear magnitudes of the bins, from 0 Hz to the Nyquist frequency.
+    pub fn get_bins(&self, channel: AudioChannel, view: SpectrumView) -> &[f32] {
+        &self.bins[view as usize][channel as usize]
+    }
+
+    pub fn get_fft_resolution(&self) -> f32 {
+        self.fft_resolution
+    }
+
+    /// Energy of every pitch class, starting from C, relative to the strongest one.
+    pub fn get_chroma(&self, channel: AudioChannel) -> &Chroma {
+        &self.chroma[channel as usize]
+    }
+
+    /// The strongest pitch class, or `None` on silence.
+    pub fn get_dominant_pitch(&self, channel: AudioChannel) -> Option<PitchClass> {
+        dominant_pitch(&self.chroma[channel as usize])
+    }
+
+    /// The key estimated from the last seconds of the mid channel.
+    pub fn get_key(&self) -> Option<(PitchClass, KeyMode)> {
+        self.key
     }
 
-    pub fn get_frequency_amplitude(&self, frequency: f32) -> Option<f32> {
+    // Only the raw spectrum is converted, every other view is already in [0, 1]
+    fn convert_magnitude(&self, magnitude: f32, view: SpectrumView) -> f32 {
+        match view {
+            SpectrumView::Raw => self.scale.convert_magnitude(magnitude),
+            _ => magnitude,
+        }
+    }
+
+    fn compute_bands(
+        &self,
+        band_count: usize,
+        scale: BandScale,
+        channel: AudioChannel,
+        view: SpectrumView,
+    ) -> Vec<f32> {
+        let (min_frequency, max_frequency) = self.band_frequency_range;
+        let min_value = band_scale_from_frequency(scale, min_frequency);
+        let max_value = band_scale_from_frequency(scale, max_frequency);
+        let band_width = (max_value - min_value) / band_count as f32;
+        (0..band_count)
+            .map(|band| {
+                let lower_frequency =
+                    band_scale_to_frequency(scale, min_value + band as f32 * band_width);
+                let upper_frequency =
+                    band_scale_to_frequency(scale, min_value + (band + 1) as f32 * band_width);
+                self.get_average_amplitude(lower_frequency, upper_frequency, channel, view)
+                    .unwrap_or_default()
+            })
+            .collect()
+    }
+
+    fn get_frequency_magnitude(&self, frequency: f32, bins: &[f32]) -> Option<f32> {
         let lower_index = (frequency / self.fft_resolution) as usize;
         let upper_index = lower_index + 1;
         let precise_index = frequency / self.fft_resolution;
         Some(
-            self.raw_bins.get(lower_index)?
+            bins.get(lower_index)?
                 + (precise_index - lower_index as f32)
-                    * (self.raw_bins.get(upper_index)? - self.raw_bins.get(lower_index)?),
+                    * (bins.get(upper_index)? - bins.get(lower_index)?),
         )
     }
 
-    pub fn get_average_amplitude(&self, lower_frequency: f32, upper_frequency: f32) -> Option<f32> {
-        Some(
-            self.get_area_under_curve(lower_frequency, upper_frequency)?
-                / (upper_frequency - lower_frequency),
-        )
-    }
-
-    fn get_area_under_curve(&self, lower_frequency: f32, upper_frequency: f32) -> Option<f32> {
+    fn get_area_under_curve(
+        &self,
+        lower_frequency: f32,
+        upper_frequency: f32,
+        bins: &[f32],
+    ) -> Option<f32> {
         if lower_frequency > upper_frequency {
             return None;
         }
@@ -58,24 +244,24 @@ impl FftResult {
 
         if low_known_index > upper_known_index {
             return Some(
-                (self.get_frequency_amplitude(lower_frequency)?
-                    + self.get_frequency_amplitude(upper_frequency)?)
+                (self.get_frequency_magnitude(lower_frequency, bins)?
+                    + self.get_frequency_magnitude(upper_frequency, bins)?)
                     / 2.0f32
                     * (upper_frequency - lower_frequency),
             );
         }
 
-        let lower_partial_area = (self.get_frequency_amplitude(lower_frequency)?
-            + self.raw_bins.get(low_known_index)?)
+        let lower_partial_area = (self.get_frequency_magnitude(lower_frequency, bins)?
+            + bins.get(low_known_index)?)
             / 2.0f32
             * (self.get_bin_frequency_at_index(low_known_index) - lower_frequency);
 
-        let upper_partial_area = (self.get_frequency_amplitude(upper_frequency)?
-            + self.raw_bins.get(upper_known_index)?)
+        let upper_partial_area = (self.get_frequency_magnitude(upper_frequency, bins)?
+            + bins.get(upper_known_index)?)
             / 2.0f32
             * (upper_frequency - self.get_bin_frequency_at_index(upper_known_index));
 
-        let area_no_lerp = self.raw_bins[low_known_index..=upper_known_index]
+        let area_no_lerp = bins[low_known_index..=upper_known_index]
             .windows(2)
             .map(|slice| (slice[0] + slice[1]) / 2.0f32 * self.fft_resolution)
             .sum::<f32>();
@@ -88,79 +274,435 @@ impl FftResult {
     }
 }
 
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub enum WindowFunction {
+    #[default]
+    Hann,
+    Hamming,
+    BlackmanHarris,
+    /// Wide main lobe but very accurate amplitudes. Good for measuring levels.
+    FlatTop,
+}
+
+impl WindowFunction {
+    fn coefficients(&self, size: usize) -> Vec<f32> {
+        // All of these are generalized cosine windows: sum of a_k * cos(2 * pi * k * n / (N - 1))
+        let cosine_coefficients: &[f32] = match self {
+            WindowFunction::Hann => &[0.5, -0.5],
+            WindowFunction::Hamming => &[0.54, -0.46],
+            WindowFunction::BlackmanHarris => &[0.35875, -0.48829, 0.14128, -0.01168],
+            WindowFunction::FlatTop => &[
+                0.21557895,
+                -0.41663158,
+                0.27726316,
+                -0.083578947,
+                0.006947368,
+            ],
+        };
+
+        (0..size)
+            .map(|index| {
+                let phase = 2.0 * std::f32::consts::PI * index as f32 / (size as f32 - 1.0);
+                cosine_coefficients
+                    .iter()
+                    .enumerate()
+                    .map(|(k, coefficient)| coefficient * (k as f32 * phase).cos())
+                    .sum()
+            })
+            .collect()
+    }
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FftConfig {
+    /// Number of samples per FFT, at least 2. The frequency resolution is `sample_rate / size`.
+    pub size: usize,
+    /// Number of new samples between two FFTs. When `None`, a single FFT is computed per tick.
+    pub hop_size: Option<usize>,
+    pub window: WindowFunction,
+    pub scale: SpectrumScale,
+    /// Frequency range covered by the bands returned by `FftResult::with_bands`.
+    pub band_min_frequency: f32,
+    pub band_max_frequency: f32,
+}
+
+impl Default for FftConfig {
+    fn default() -> Self {
+        Self {
+            size: 1024,
+            hop_size: None,
+            window: WindowFunction::default(),
+            scale: SpectrumScale::default(),
+            band_min_frequency: 20.0,
+            band_max_frequency: 16000.0,
+        }
+    }
+}
+
+/// Everything computed by an `AudioSignalProcessor`, shared with the effects.
+#[derive(Clone)]
+pub struct AnalysisResults {
+    pub fft_result: Arc<RwLock<FftResult>>,
+    pub beat_result: Arc<RwLock<BeatResult>>,
+    pub signal_features: Arc<RwLock<SignalFeatures>>,
+}
+
+impl std::fmt::Debug for AnalysisResults {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AnalysisResults").finish_non_exhaustive()
+    }
+}
+
 pub struct AudioSignalProcessor {
-    audio_sample_buffer: dasp_ring_buffer::Fixed<Vec<f32>>,
-    audio_sample_rx: ringbuf::HeapConsumer<f32>,
+    // One ring buffer per input channel, holding the deinterleaved samples
+    channel_sample_buffers: Vec<dasp_ring_buffer::Fixed<Vec<f32>>>,
+    audio_sample_rx: SampleConsumer,
+    // Overruns and skipped samples at the last report
+    reported_sample_losses: (u64, u64),
+    last_buffer_stats_log: Instant,
+    // Input channel that the next received sample belongs to
+    next_channel: usize,
+    // Frames received since the last FFT
+    pending_frame_count: usize,
+    last_sample_time: Instant,
+    last_analysis_time: Instant,
     tmp_vec: Vec<f32>,
+    // Samples of the channel being analyzed, before windowing
+    channel_samples: Vec<f32>,
     fft_plan: Arc<dyn rustfft::Fft<f32>>,
     fft_compute_buffer: Vec<Complex<f32>>,
     fft_window_buffer: Vec<Complex<f32>>,
+    window: Vec<f32>,
+    // Sum of the window coefficients, used to compensate for the amplitude lost to windowing
+    window_sum: f32,
     fft_buffer_size: usize,
+    hop_size: Option<usize>,
+    sample_rate: u32,
+    beat_detector: BeatDetector,
+    tempo_tracker: TempoTracker,
+    gain_control: AutomaticGainControl,
+    spectrum_smoothing: SpectrumSmoothing,
+    chroma_extractor: ChromaExtractor,
+    key_tracker: KeyTracker,
     pub fft_result: Arc<RwLock<FftResult>>,
+    pub beat_result: Arc<RwLock<BeatResult>>,
+    pub signal_features: Arc<RwLock<SignalFeatures>>,
 }
 
 impl AudioSignalProcessor {
     pub fn new(
-        audio_rx: ringbuf::HeapConsumer<f32>,
+        audio_rx: SampleConsumer,
         sample_rate: u32,
-        fft_buffer_size: usize,
+        channel_count: u16,
+        fft_config: FftConfig,
+        agc_config: AgcConfig,
+        smoothing_config: SmoothingConfig,
     ) -> Self {
+        let channel_count = channel_count.max(1) as usize;
+        let fft_config = if fft_config.size < 2 {
+            let default_size = FftConfig::default().size;
+            log::warn!(
+                "Ignoring FFT size of {}. Using {default_size} samples instead.",
+                fft_config.size
+            );
+            FftConfig {
+                size: default_size,
+                ..fft_config
+            }
+        } else {
+            fft_config
+        };
+        let fft_buffer_size = fft_config.size;
+        let hop_size = fft_config.hop_size.filter(|hop_size| {
+            if *hop_size == 0 {
+                log::warn!("Ignoring hop size of 0. Computing one FFT per tick instead.");
+            }
+            *hop_size > 0
+        });
+        log::info!(
+            "FFT of {fft_buffer_size} samples ({} Hz per bin) with a {:?} window",
+            sample_rate as f32 / fft_buffer_size as f32,
+            fft_config.window
+        );
+
+        let bin_count = fft_buffer_size / 2 + 1;
+        let fft_resolution = sample_rate as f32 / fft_buffer_size as f32;
+        let window = fft_config.window.coefficients(fft_buffer_size);
         let mut planner = rustfft::FftPlanner::new();
         Self {
-            audio_sample_buffer: dasp_ring_buffer::Fixed::from(vec![0_f32; fft_buffer_size]),
+            channel_sample_buffers: (0..channel_count)
+                .map(|_| dasp_ring_buffer::Fixed::from(vec![0_f32; fft_buffer_size]))
+                .collect(),
             audio_sample_rx: audio_rx,
-            tmp_vec: vec![0f32; fft_buffer_size],
+            reported_sample_losses: (0, 0),
+            last_buffer_stats_log: Instant::now(),
+            next_channel: 0,
+            pending_frame_count: 0,
+            last_sample_time: Instant::now(),
+            last_analysis_time: Instant::now(),
+            tmp_vec: vec![0f32; fft_buffer_size * channel_count],
+            channel_samples: Vec::with_capacity(fft_buffer_size),
             fft_compute_buffer: vec![Complex::<f32>::default(); fft_buffer_size],
             fft_plan: planner.plan_fft_forward(fft_buffer_size),
             fft_window_buffer: vec![],
+            window_sum: window.iter().sum(),
+            window,
             fft_buffer_size,
-            fft_result: Arc::new(RwLock::new(FftResult::new(
-                vec![0.0f32; fft_buffer_size],
-                sample_rate as f32 / fft_buffer_size as f32,
-            ))),
+            hop_size,
+            sample_rate,
+            beat_detector: BeatDetector::new(),
+            tempo_tracker: TempoTracker::new(),
+            gain_control: AutomaticGainControl::new(agc_config, bin_count, fft_resolution),
+            spectrum_smoothing: SpectrumSmoothing::new(smoothing_config, bin_count, fft_resolution),
+            chroma_extractor: ChromaExtractor::new(bin_count, fft_resolution),
+            key_tracker: KeyTracker::new(),
+            fft_result: Arc::new(RwLock::new(FftResult::new(sample_rate, &fft_config))),
+            beat_result: Default::default(),
+            signal_features: Default::default(),
         }
     }
 
     pub fn compute_fft(&mut self) {
-        let sample_count = self.audio_sample_rx.pop_slice(self.tmp_vec.as_mut_slice());
+        self.log_buffer_stats();
+        self.beat_result.write().unwrap().clear_events();
+
+        // When falling behind, skip to the newest samples that fit in a single FFT. Only whole
+        // frames are skipped so that the channels stay in order
+        let channel_count = self.channel_sample_buffers.len();
+        let max_sample_count = self.fft_buffer_size * channel_count;
+        let pending_sample_count = self.audio_sample_rx.len();
+        if pending_sample_count > max_sample_count {
+            let late_frame_count =
+                (pending_sample_count - max_sample_count).div_ceil(channel_count);
+            self.audio_sample_rx.skip(late_frame_count * channel_count);
+        }
+
+        let sample_count = self
+            .audio_sample_rx
+            .pop_slice(&mut self.tmp_vec[..max_sample_count]);
         if sample_count == 0 {
-            self.audio_sample_buffer.iter_mut().for_each(|x| *x = 0.0);
-        } else {
-            self.tmp_vec.iter().take(sample_count).for_each(|sample| {
-                self.audio_sample_buffer.push(*sample);
-            });
+            self.audio_sample_rx.count_underrun();
+            // Keep the last spectrum through short gaps, and let the smoothed views decay over
+            // real time once the input went silent
+            if self.last_sample_time.elapsed() > MAX_SAMPLE_GAP {
+                self.channel_sample_buffers
+                    .iter_mut()
+                    .for_each(|buffer| buffer.iter_mut().for_each(|x| *x = 0.0));
+                self.analyze(self.last_analysis_time.elapsed().as_secs_f32());
+            }
+            return;
         }
+        self.last_sample_time = Instant::now();
+        self.process_samples(sample_count);
+    }
 
-        self.fft_window_buffer.clear();
-        self.fft_window_buffer.extend(
-            dasp_signal::from_iter(
-                self.audio_sample_buffer
+    /// Analyzes the next `sample_count` received samples, or all of them if fewer were received.
+    /// Unlike `compute_fft`, the result only depends on the samples and not on when they arrived,
+    /// which makes offline rendering reproducible.
+    pub fn compute_fft_exact(&mut self, sample_count: usize) {
+        self.beat_result.write().unwrap().clear_events();
+        if self.tmp_vec.len() < sample_count {
+            self.tmp_vec.resize(sample_count, 0.0);
+        }
+        let sample_count = self
+            .audio_sample_rx
+            .pop_slice(&mut self.tmp_vec[..sample_count]);
+        self.process_samples(sample_count);
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Number of interleaved channels in the received samples.
+    pub fn channel_count(&self) -> u16 {
+        self.channel_sample_buffers.len() as u16
+    }
+
+    /// Number of received samples that weren't analyzed yet.
+    pub fn pending_sample_count(&self) -> usize {
+        self.audio_sample_rx.len()
+    }
+
+    // Dispatches the first `sample_count` samples of `tmp_vec` and analyzes them
+    fn process_samples(&mut self, sample_count: usize) {
+        // The stream is interleaved, so dispatch every sample to its channel's buffer
+        let channel_count = self.channel_sample_buffers.len();
+        for index in 0..sample_count {
+            self.channel_sample_buffers[self.next_channel].push(self.tmp_vec[index]);
+            self.next_channel = (self.next_channel + 1) % channel_count;
+            if self.next_channel != 0 {
+                continue;
+            }
+
+            self.pending_frame_count += 1;
+            if Some(self.pending_frame_count) == self.hop_size {
+                self.analyze(self.pending_frame_count as f32 / self.sample_rate as f32);
+                self.pending_frame_count = 0;
+            }
+        }
+
+        if self.hop_size.is_none() {
+            self.analyze(self.pending_frame_count as f32 / self.sample_rate as f32);
+            self.pending_frame_count = 0;
+        }
+    }
+
+    fn log_buffer_stats(&mut self) {
+        if self.last_buffer_stats_log.elapsed() < BUFFER_STATS_LOG_INTERVAL {
+            return;
+        }
+        self.last_buffer_stats_log = Instant::now();
+
+        let stats = self.audio_sample_rx.stats();
+        let sample_losses = (stats.overruns(), stats.skipped());
+        if sample_losses != self.reported_sample_losses {
+            log::warn!(
+                "Audio buffer: {} frames dropped because it was full, {} samples skipped to catch \
+                 up, {} ticks without samples",
+                sample_losses.0,
+                sample_losses.1,
+                stats.underruns()
+            );
+            self.reported_sample_losses = sample_losses;
+        }
+    }
+
+    /// Handles to the results, for the effects reading this processor.
+    pub fn results(&self) -> AnalysisResults {
+        AnalysisResults {
+            fft_result: self.fft_result.clone(),
+            beat_result: self.beat_result.clone(),
+            signal_features: self.signal_features.clone(),
+        }
+    }
+
+    /// Forces the tempo to `bpm` instead of estimating it from the audio.
+    pub fn set_fixed_bpm(&mut self, bpm: Option<f32>) {
+        self.tempo_tracker.set_fixed_bpm(bpm);
+    }
+
+    /// Taps the tempo, which overrides the estimated one.
+    pub fn tap_tempo(&mut self, time: Instant) {
+        self.tempo_tracker.tap(time);
+    }
+
+    // Computes the FFT of the samples currently in the buffers and updates everything that depends
+    // on it. `elapsed_seconds` is the duration of audio since the previous analysis.
+    fn analyze(&mut self, elapsed_seconds: f32) {
+        self.last_analysis_time = Instant::now();
+        let fft_result = self.fft_result.clone();
+        let mut fft_result = fft_result.write().unwrap();
+        let signal_features = self.signal_features.clone();
+        let mut signal_features = signal_features.write().unwrap();
+        for channel in AudioChannel::ALL {
+            self.fill_window_buffer(channel);
+            self.fft_plan
+                .process_with_scratch(&mut self.fft_window_buffer, &mut self.fft_compute_buffer);
+
+            // The upper half of the spectrum mirrors the lower half since the input is real. Its
+            // energy is folded into the lower half by doubling every bin except 0 Hz and Nyquist.
+            let bin_count = self.fft_buffer_size / 2 + 1;
+            let nyquist_index = self
+                .fft_buffer_size
+                .is_multiple_of(2)
+                .then_some(bin_count - 1);
+            let raw_bins = &mut fft_result.bins[SpectrumView::Raw as usize][channel as usize];
+            raw_bins.clear();
+            raw_bins.extend(
+                self.fft_window_buffer
                     .iter()
-                    .map(|e| e.to_sample::<f32>()),
-            )
-            .scale_amp(1.0)
-            .take(self.fft_buffer_size)
-            .enumerate()
-            .map(|(index, value)| {
-                let hann_factor = dasp_window::Hanning::window(
-                    index as f32 / (self.fft_buffer_size as f32 - 1.0),
-                );
-                Complex::<f32> {
-                    re: value * hann_factor,
-                    im: 0.0,
-                }
-            }),
+                    .take(bin_count)
+                    .enumerate()
+                    .map(|(index, bin)| {
+                        let one_sided_factor = if index == 0 || Some(index) == nyquist_index {
+                            1.0
+                        } else {
+                            2.0
+                        };
+                        bin.norm() * one_sided_factor / self.window_sum
+                    }),
+            );
+
+            let mut normalized_bins = std::mem::take(
+                &mut fft_result.bins[SpectrumView::Normalized as usize][channel as usize],
+            );
+            self.gain_control.process(
+                channel,
+                &fft_result.bins[SpectrumView::Raw as usize][channel as usize],
+                &mut normalized_bins,
+                elapsed_seconds,
+            );
+
+            let mut smoothed_bins = std::mem::take(
+                &mut fft_result.bins[SpectrumView::Smoothed as usize][channel as usize],
+            );
+            let mut peak_bins = std::mem::take(
+                &mut fft_result.bins[SpectrumView::PeakHold as usize][channel as usize],
+            );
+            self.spectrum_smoothing.process(
+                channel,
+                &normalized_bins,
+                &mut smoothed_bins,
+                &mut peak_bins,
+                elapsed_seconds,
+            );
+            fft_result.chroma[channel as usize] = self
+                .chroma_extractor
+                .compute(&fft_result.bins[SpectrumView::Raw as usize][channel as usize]);
+            signal_features.update(
+                channel,
+                &self.channel_samples,
+                &fft_result.bins[SpectrumView::Raw as usize][channel as usize],
+                fft_result.fft_resolution,
+            );
+
+            fft_result.bins[SpectrumView::Normalized as usize][channel as usize] = normalized_bins;
+            fft_result.bins[SpectrumView::Smoothed as usize][channel as usize] = smoothed_bins;
+            fft_result.bins[SpectrumView::PeakHold as usize][channel as usize] = peak_bins;
+        }
+
+        fft_result.bands_cache.get_mut().unwrap().clear();
+        fft_result.key = self.key_tracker.update(
+            &fft_result.chroma[AudioChannel::Mid as usize],
+            elapsed_seconds,
         );
 
-        self.fft_plan
-            .process_with_scratch(&mut self.fft_window_buffer, &mut self.fft_compute_buffer);
+        let mut beat_result = self.beat_result.write().unwrap();
+        let (onset_strength, is_beat) =
+            self.beat_detector
+                .update(&fft_result, elapsed_seconds, &mut beat_result);
+        self.tempo_tracker
+            .update(onset_strength, is_beat, elapsed_seconds, &mut beat_result);
+    }
+
+    fn fill_window_buffer(&mut self, channel: AudioChannel) {
+        // Mono inputs use their only channel as both left and right
+        let left = &self.channel_sample_buffers[0];
+        let right = self.channel_sample_buffers.get(1).unwrap_or(left);
+        let samples = left
+            .iter()
+            .zip(right.iter())
+            .map(|(left, right)| match channel {
+                AudioChannel::Left => *left,
+                AudioChannel::Right => *right,
+                AudioChannel::Mid => (left + right) / 2.0,
+                AudioChannel::Side => (left - right) / 2.0,
+            });
+        self.channel_samples.clear();
+        self.channel_samples.extend(samples);
 
-        let mut fft_result = self.fft_result.write().unwrap();
-        fft_result.raw_bins.clear();
-        fft_result.raw_bins.extend(
-            self.fft_window_buffer
-                .iter()
-                .map(|bin| bin.norm_sqr() / (self.fft_buffer_size as f32).sqrt()),
+        self.fft_window_buffer.clear();
+        self.fft_window_buffer.extend(
+            dasp_signal::from_iter(self.channel_samples.iter().map(|e| e.to_sample::<f32>()))
+                .scale_amp(1.0)
+                .take(self.fft_buffer_size)
+                .zip(self.window.iter())
+                .map(|(value, window_factor)| Complex::<f32> {
+                    re: value * window_factor,
+                    im: 0.0,
+                }),
         );
     }
 }
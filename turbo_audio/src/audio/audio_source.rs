@@ -0,0 +1,76 @@
+use crate::audio::{
+    audio_stream::{DeviceSupervisor, InputConfig},
+    file_source::{FileSource, FileSourceConfig},
+    generator_source::{GeneratorSource, GeneratorSourceConfig},
+    pipewire_source::{PipewireSource, PipewireSourceConfig},
+    sample_buffer::SampleConsumer,
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub enum AudioSourceConfig {
+    /// Captures the input device named in the config, or the default one.
+    #[default]
+    Device,
+    File(FileSourceConfig),
+    /// Creates its own pipewire capture node, at the configured sample rate and channel count
+    /// (stereo by default).
+    Pipewire(PipewireSourceConfig),
+    /// Generates a test signal at the configured sample rate and channel count (stereo by
+    /// default).
+    Generator(GeneratorSourceConfig),
+}
+
+/// Where the analyzed samples come from. Samples stop arriving once it is dropped.
+// The device supervisor and the pipewire and generator sources are only held to keep them running
+#[allow(dead_code)]
+pub enum AudioSource {
+    Device(DeviceSupervisor),
+    File(FileSource),
+    Pipewire(PipewireSource),
+    Generator(GeneratorSource),
+}
+
+impl AudioSource {
+    /// Starts the source. Returns the consumer of its interleaved samples, their channel count and
+    /// their sample rate. `device_name` is only used by device sources, `sample_rate` and
+    /// `input_config` by device, pipewire and generator sources.
+    pub fn start(
+        config: &AudioSourceConfig,
+        device_name: Option<String>,
+        sample_rate: u32,
+        input_config: InputConfig,
+    ) -> anyhow::Result<(Self, SampleConsumer, u16, u32)> {
+        match config {
+            AudioSourceConfig::Device => {
+                let (supervisor, rx, channel_count, sample_rate) =
+                    DeviceSupervisor::start(device_name, sample_rate, input_config)?;
+                Ok((Self::Device(supervisor), rx, channel_count, sample_rate))
+            }
+            AudioSourceConfig::File(file_config) => {
+                let (source, rx, channel_count, sample_rate) = FileSource::start(file_config)?;
+                Ok((Self::File(source), rx, channel_count, sample_rate))
+            }
+            AudioSourceConfig::Pipewire(pipewire_config) => {
+                let channel_count = input_config.channels.unwrap_or(2);
+                let (source, rx) =
+                    PipewireSource::start(pipewire_config, channel_count, sample_rate)?;
+                Ok((Self::Pipewire(source), rx, channel_count, sample_rate))
+            }
+            AudioSourceConfig::Generator(generator_config) => {
+                let channel_count = input_config.channels.unwrap_or(2);
+                let (source, rx) =
+                    GeneratorSource::start(generator_config, channel_count, sample_rate);
+                Ok((Self::Generator(source), rx, channel_count, sample_rate))
+            }
+        }
+    }
+
+    /// Whether the source won't produce any more samples.
+    pub fn is_finished(&self) -> bool {
+        match self {
+            Self::Device(_) | Self::Pipewire(_) | Self::Generator(_) => false,
+            Self::File(source) => source.is_finished(),
+        }
+    }
+}
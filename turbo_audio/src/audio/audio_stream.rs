@@ -1,84 +1,528 @@
-use anyhow::Context;
-use cpal::traits::{DeviceTrait, HostTrait};
+use crate::audio::sample_buffer::{sample_buffer, tick_capacity, SampleConsumer, SampleProducer};
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
 use cpal::{
-    Device, FromSample, InputCallbackInfo, SampleFormat, StreamConfig, SupportedStreamConfig,
+    BufferSize, Device, FromSample, Host, InputCallbackInfo, SampleFormat, StreamConfig,
+    StreamError, SupportedBufferSize,
 };
-use retry::{delay::Exponential, retry_with_index};
-use ringbuf::{HeapConsumer, HeapProducer};
+use retry::delay::Exponential;
+use serde::{Deserialize, Serialize};
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+use thiserror::Error;
 
-pub fn start_audio_loop(
-    device_name: Option<String>,
-    sample_rate: u32,
-) -> anyhow::Result<(cpal::Stream, HeapConsumer<f32>)> {
-    let audio_device = get_audio_device(device_name);
-    let input_config = get_input_config(&audio_device, sample_rate);
-    let sample_format = input_config.sample_format();
-    let config: StreamConfig = input_config.into();
-    let max_retries: usize = 3;
-    retry_with_index(
-        Exponential::from_millis(250).take(max_retries),
-        |retry_attempt| {
-            let stream_result = start_stream(&config, &audio_device, &sample_format);
-            match stream_result {
-                Ok(result) => {
-                    log::trace!("Started audio stream");
-                    Ok(result)
+// Channel count assumed when no device could be opened at startup
+const DEFAULT_CHANNEL_COUNT: u16 = 2;
+// How often the supervisor checks on the stream
+const SUPERVISOR_POLL_INTERVAL: Duration = Duration::from_millis(100);
+// How often the supervisor looks for the configured device while the default input stands in for
+// it. Listing the devices briefly opens every one of them, so it is never done otherwise
+const DEVICE_CHECK_INTERVAL: Duration = Duration::from_secs(2);
+// Longest wait between two attempts at reopening the device
+const MAX_REOPEN_DELAY: Duration = Duration::from_secs(10);
+// Sample formats that can be captured, from the most to the least accurate once converted to f32.
+// F32 comes first since it needs no conversion
+const SAMPLE_FORMAT_PREFERENCE: [SampleFormat; 10] = [
+    SampleFormat::F32,
+    SampleFormat::F64,
+    SampleFormat::I32,
+    SampleFormat::U32,
+    SampleFormat::I64,
+    SampleFormat::U64,
+    SampleFormat::I16,
+    SampleFormat::U16,
+    SampleFormat::I8,
+    SampleFormat::U8,
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum InputSampleFormat {
+    I8,
+    I16,
+    I32,
+    I64,
+    U8,
+    U16,
+    U32,
+    U64,
+    F32,
+    F64,
+}
+
+impl From<InputSampleFormat> for SampleFormat {
+    fn from(format: InputSampleFormat) -> Self {
+        match format {
+            InputSampleFormat::I8 => SampleFormat::I8,
+            InputSampleFormat::I16 => SampleFormat::I16,
+            InputSampleFormat::I32 => SampleFormat::I32,
+            InputSampleFormat::I64 => SampleFormat::I64,
+            InputSampleFormat::U8 => SampleFormat::U8,
+            InputSampleFormat::U16 => SampleFormat::U16,
+            InputSampleFormat::U32 => SampleFormat::U32,
+            InputSampleFormat::U64 => SampleFormat::U64,
+            InputSampleFormat::F32 => SampleFormat::F32,
+            InputSampleFormat::F64 => SampleFormat::F64,
+        }
+    }
+}
+
+/// Preferences used to pick among the configs supported by the input device. The sample rate is
+/// the `sample_rate` of the settings. When a preference can't be met, the closest config is used.
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct InputConfig {
+    /// Channel count. The device's default channel count is preferred when unset.
+    pub channels: Option<u16>,
+    /// The device's default sample format is preferred when unset.
+    pub sample_format: Option<InputSampleFormat>,
+    /// Frames per buffer. Left to the driver when unset.
+    pub buffer_size: Option<u32>,
+}
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("No audio device named {0}")]
+    DeviceNotFound(String),
+
+    #[error("No default audio input device")]
+    NoDefaultDevice,
+
+    #[error("Couldn't get the supported input configs: {0}")]
+    SupportedConfigs(#[from] cpal::SupportedStreamConfigsError),
+
+    #[error("The audio device has no input config in a supported sample format")]
+    NoInputConfig,
+
+    #[error("The audio device has no input config with {0} channels at {1} Hz")]
+    NoMatchingInputConfig(u16, u32),
+
+    #[error("Unsupported sample format: {0}")]
+    UnsupportedSampleFormat(SampleFormat),
+
+    #[error("Couldn't build the input stream: {0}")]
+    BuildStream(#[from] cpal::BuildStreamError),
+
+    #[error("Couldn't start the input stream: {0}")]
+    PlayStream(#[from] cpal::PlayStreamError),
+
+    #[error("The audio device supervisor stopped unexpectedly")]
+    SupervisorStopped,
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Keeps an input device captured. When the stream fails or the device disappears, the device is
+/// reopened with an exponential backoff, falling back to the default input in the meantime. No
+/// samples are pushed while no device is open, so the effects keep running on silence.
+pub struct DeviceSupervisor {
+    should_stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for DeviceSupervisor {
+    fn drop(&mut self) {
+        self.should_stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl DeviceSupervisor {
+    /// Starts capturing audio. The samples are pushed interleaved in the returned consumer, so the
+    /// stream's channel count and sample rate are returned alongside it. Later streams are opened
+    /// with the same channel count and sample rate.
+    pub fn start(
+        device_name: Option<String>,
+        sample_rate: u32,
+        input_config: InputConfig,
+    ) -> Result<(Self, SampleConsumer, u16, u32)> {
+        let should_stop = Arc::new(AtomicBool::new(false));
+        let (rx_tx, rx_rx) = mpsc::channel();
+        // cpal streams can't be sent between threads, so they live on the supervisor's thread
+        let thread = {
+            let should_stop = should_stop.clone();
+            std::thread::spawn(move || {
+                let mut supervisor = Supervisor {
+                    device_name,
+                    request: StreamRequest {
+                        sample_rate,
+                        config: input_config,
+                        is_locked: false,
+                    },
+                    tx: None,
+                    rx: None,
+                    stream_failed: Arc::new(AtomicBool::new(false)),
+                    stream: None,
+                    reopen_delays: reopen_delays(),
+                    next_open_attempt: Instant::now(),
+                    last_device_check: Instant::now(),
+                };
+                supervisor.try_open();
+                if !supervisor.request.is_locked {
+                    log::warn!(
+                        "No audio device could be opened, assuming {DEFAULT_CHANNEL_COUNT} channels"
+                    );
+                    supervisor.request.lock(DEFAULT_CHANNEL_COUNT, sample_rate);
+                    supervisor.sample_producer();
+                }
+                let (channel_count, sample_rate) = supervisor.request.format();
+                if let Some(rx) = supervisor.rx.take() {
+                    let _ = rx_tx.send((rx, channel_count, sample_rate));
                 }
-                Err(err) => {
-                    log::trace!("Failed to start audio stream");
-                    if retry_attempt < max_retries.try_into().unwrap() {
-                        log::trace!("Retrying to start audio stream...");
-                    }
-                    Err(err)
+                while !should_stop.load(Ordering::Relaxed) {
+                    std::thread::sleep(SUPERVISOR_POLL_INTERVAL);
+                    supervisor.check();
                 }
+            })
+        };
+
+        let (rx, channel_count, sample_rate) =
+            rx_rx.recv().map_err(|_| Error::SupervisorStopped)?;
+        Ok((
+            Self {
+                should_stop,
+                thread: Some(thread),
+            },
+            rx,
+            channel_count,
+            sample_rate,
+        ))
+    }
+}
+
+struct OpenStream {
+    // Only held to keep the capture running
+    _stream: cpal::Stream,
+    device_name: String,
+    // Whether the default input is used because the configured device couldn't be opened
+    is_fallback: bool,
+}
+
+#[derive(Clone, Copy)]
+struct StreamRequest {
+    sample_rate: u32,
+    config: InputConfig,
+    // Set once the layout of the samples is known. The processor is set up for it, so every later
+    // stream must have the same channel count and sample rate
+    is_locked: bool,
+}
+
+impl StreamRequest {
+    fn lock(&mut self, channel_count: u16, sample_rate: u32) {
+        self.config.channels = Some(channel_count);
+        self.sample_rate = sample_rate;
+        self.is_locked = true;
+    }
+
+    fn format(&self) -> (u16, u32) {
+        (
+            self.config.channels.unwrap_or(DEFAULT_CHANNEL_COUNT),
+            self.sample_rate,
+        )
+    }
+}
+
+struct Supervisor {
+    device_name: Option<String>,
+    request: StreamRequest,
+    // Created along with the first stream, since its size depends on the stream's format
+    tx: Option<Arc<Mutex<SampleProducer>>>,
+    // Until it is handed to the processor
+    rx: Option<SampleConsumer>,
+    stream_failed: Arc<AtomicBool>,
+    stream: Option<OpenStream>,
+    reopen_delays: Box<dyn Iterator<Item = Duration>>,
+    next_open_attempt: Instant,
+    last_device_check: Instant,
+}
+
+impl Supervisor {
+    fn check(&mut self) {
+        // A device that is unplugged makes its stream fail
+        if self.stream_failed.swap(false, Ordering::Relaxed) {
+            if let Some(stream) = &self.stream {
+                log::warn!("The stream of {} failed, reopening it", stream.device_name);
+                self.close();
             }
-        },
+        }
+
+        let is_fallback = self
+            .stream
+            .as_ref()
+            .is_some_and(|stream| stream.is_fallback);
+        if is_fallback && self.last_device_check.elapsed() >= DEVICE_CHECK_INTERVAL {
+            self.last_device_check = Instant::now();
+            if self
+                .device_name
+                .as_ref()
+                .is_some_and(|name| input_device_names().contains(name))
+            {
+                log::info!("The audio device is back, switching to it");
+                self.close();
+            }
+        }
+
+        if self.stream.is_none() && Instant::now() >= self.next_open_attempt {
+            self.try_open();
+        }
+    }
+
+    fn close(&mut self) {
+        self.stream = None;
+        self.reopen_delays = reopen_delays();
+        self.next_open_attempt = Instant::now();
+    }
+
+    // Returns the stream along with the name of its device
+    fn open_stream(&mut self, device_name: Option<&str>) -> Result<(cpal::Stream, String)> {
+        let audio_device = get_audio_device(device_name)?;
+        let (config, sample_format) = get_input_config(&audio_device, &self.request)?;
+        if !self.request.is_locked {
+            self.request.lock(config.channels, config.sample_rate.0);
+        }
+        let stream = start_stream(
+            &config,
+            &audio_device,
+            &sample_format,
+            self.sample_producer(),
+            self.stream_failed.clone(),
+        )?;
+        stream.play()?;
+        let name = audio_device
+            .name()
+            .unwrap_or_else(|_| "an unnamed device".to_owned());
+        Ok((stream, name))
+    }
+
+    // The buffer is sized for the format of the first stream, which every later stream shares
+    fn sample_producer(&mut self) -> Arc<Mutex<SampleProducer>> {
+        if let Some(tx) = &self.tx {
+            return tx.clone();
+        }
+        let (channel_count, sample_rate) = self.request.format();
+        let (tx, rx) = sample_buffer(tick_capacity(
+            sample_rate,
+            channel_count,
+            crate::TICKS_PER_SECOND,
+        ));
+        let tx = Arc::new(Mutex::new(tx));
+        self.tx = Some(tx.clone());
+        self.rx = Some(rx);
+        tx
+    }
+
+    fn try_open(&mut self) {
+        let result = match self.open_stream(self.device_name.clone().as_deref()) {
+            Ok(stream) => Ok((stream, false)),
+            Err(e) if self.device_name.is_some() => {
+                log::error!("Couldn't open the audio device: {e}. Trying the default input.");
+                self.open_stream(None).map(|stream| (stream, true))
+            }
+            Err(e) => Err(e),
+        };
+
+        match result {
+            Ok(((stream, device_name), is_fallback)) => {
+                log::info!("Capturing audio from {device_name}");
+                self.stream_failed.store(false, Ordering::Relaxed);
+                self.stream = Some(OpenStream {
+                    _stream: stream,
+                    device_name,
+                    is_fallback,
+                });
+                self.reopen_delays = reopen_delays();
+            }
+            Err(e) => {
+                let delay = self.reopen_delays.next().unwrap_or(MAX_REOPEN_DELAY);
+                log::error!(
+                    "Couldn't open an audio device: {e}. Retrying in {:.1}s.",
+                    delay.as_secs_f32()
+                );
+                self.next_open_attempt = Instant::now() + delay;
+            }
+        }
+    }
+}
+
+fn reopen_delays() -> Box<dyn Iterator<Item = Duration>> {
+    Box::new(Exponential::from_millis(250).map(|delay| delay.min(MAX_REOPEN_DELAY)))
+}
+
+// The hosts listed by `--list-devices`, the default one first
+fn available_hosts() -> impl Iterator<Item = Host> {
+    let default_host = cpal::default_host();
+    let default_host_id = default_host.id();
+    std::iter::once(default_host).chain(
+        cpal::available_hosts()
+            .into_iter()
+            .filter(move |&host_id| host_id != default_host_id)
+            .filter_map(|host_id| cpal::host_from_id(host_id).ok()),
     )
-    .with_context(|| "Failed to start stream")
 }
 
-fn get_audio_device(device_name: Option<String>) -> Device {
-    let host = cpal::default_host();
+fn input_devices() -> impl Iterator<Item = Device> {
+    available_hosts().flat_map(|host| host.input_devices().into_iter().flatten())
+}
+
+fn input_device_names() -> Vec<String> {
+    input_devices()
+        .filter_map(|device| device.name().ok())
+        .collect()
+}
 
+fn get_audio_device(device_name: Option<&str>) -> Result<Device> {
     match device_name {
-        Some(device_name) => host
-            .devices()
-            .expect("Host has no audio device")
-            .find(|device| device.name().unwrap() == device_name)
-            .unwrap_or_else(|| panic!("No suitable audio device found with name {device_name}")),
-        None => host
+        Some(device_name) => input_devices()
+            .find(|device| device.name().is_ok_and(|name| name == device_name))
+            .ok_or_else(|| Error::DeviceNotFound(device_name.to_owned())),
+        None => cpal::default_host()
             .default_input_device()
-            .expect("No default audio input found"),
+            .ok_or(Error::NoDefaultDevice),
     }
 }
 
-fn get_input_config(audio_device: &Device, sample_rate: u32) -> SupportedStreamConfig {
-    audio_device
-        .supported_input_configs()
-        .expect("Device has no supported input configs")
-        .next()
-        .expect("Device has no supported input configs")
-        .with_sample_rate(cpal::SampleRate(sample_rate))
+// Ordered by importance: a wrong sample rate or channel count matters more than a less accurate
+// sample format or an unsupported buffer size
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct ConfigScore {
+    sample_rate_distance: u32,
+    channel_mismatch: bool,
+    // 0 for the preferred format, then by accuracy
+    sample_format_penalty: usize,
+    buffer_size_mismatch: bool,
+}
+
+// Scores every supported config against the request and returns the best one
+fn get_input_config(
+    audio_device: &Device,
+    request: &StreamRequest,
+) -> Result<(StreamConfig, SampleFormat)> {
+    let default_config = audio_device.default_input_config().ok();
+    let wanted_channels = request
+        .config
+        .channels
+        .or(default_config.as_ref().map(|config| config.channels()));
+    let wanted_format = request
+        .config
+        .sample_format
+        .map(SampleFormat::from)
+        .or(default_config.as_ref().map(|config| config.sample_format()));
+
+    let (score, config, buffer_size) = audio_device
+        .supported_input_configs()?
+        .filter_map(|range| {
+            let format_rank = SAMPLE_FORMAT_PREFERENCE
+                .iter()
+                .position(|format| *format == range.sample_format())?;
+            let sample_rate = request
+                .sample_rate
+                .clamp(range.min_sample_rate().0, range.max_sample_rate().0);
+            let buffer_size = request
+                .config
+                .buffer_size
+                .filter(|size| match range.buffer_size() {
+                    SupportedBufferSize::Range { min, max } => (*min..=*max).contains(size),
+                    SupportedBufferSize::Unknown => true,
+                });
+            let score = ConfigScore {
+                sample_rate_distance: sample_rate.abs_diff(request.sample_rate),
+                channel_mismatch: wanted_channels.is_some_and(|count| count != range.channels()),
+                sample_format_penalty: if Some(range.sample_format()) == wanted_format {
+                    0
+                } else {
+                    1 + format_rank
+                },
+                buffer_size_mismatch: buffer_size.is_none() && request.config.buffer_size.is_some(),
+            };
+            if request.is_locked && (score.sample_rate_distance > 0 || score.channel_mismatch) {
+                return None;
+            }
+            Some((
+                score,
+                range.with_sample_rate(cpal::SampleRate(sample_rate)),
+                buffer_size,
+            ))
+        })
+        .min_by_key(|(score, ..)| *score)
+        .ok_or_else(|| {
+            if request.is_locked {
+                let (channel_count, sample_rate) = request.format();
+                Error::NoMatchingInputConfig(channel_count, sample_rate)
+            } else {
+                Error::NoInputConfig
+            }
+        })?;
+
+    let sample_format = config.sample_format();
+    log::info!(
+        "Picked the input config: {} channels, {sample_format}, {} Hz",
+        config.channels(),
+        config.sample_rate().0
+    );
+    if score.sample_rate_distance > 0 {
+        log::info!(
+            "The device doesn't support {} Hz, using the closest sample rate",
+            request.sample_rate
+        );
+    }
+    if let Some(wanted_channels) = wanted_channels.filter(|_| score.channel_mismatch) {
+        log::info!("The device has no config with {wanted_channels} channels");
+    }
+    if let Some(wanted_format) = wanted_format.filter(|_| score.sample_format_penalty > 0) {
+        log::info!("The device has no config in {wanted_format}, using the most accurate format");
+    }
+    if let Some(buffer_size) = request
+        .config
+        .buffer_size
+        .filter(|_| score.buffer_size_mismatch)
+    {
+        log::info!(
+            "The device doesn't support buffers of {buffer_size} frames, leaving it to the driver"
+        );
+    }
+
+    let mut config: StreamConfig = config.into();
+    if let Some(buffer_size) = buffer_size {
+        config.buffer_size = BufferSize::Fixed(buffer_size);
+    }
+    Ok((config, sample_format))
 }
 
 fn build_audio_stream<T: cpal::Sample + cpal::SizedSample>(
     audio_device: &Device,
     config: &StreamConfig,
-    mut tx: HeapProducer<f32>,
-) -> Result<cpal::Stream, cpal::BuildStreamError>
+    tx: Arc<Mutex<SampleProducer>>,
+    stream_failed: Arc<AtomicBool>,
+) -> std::result::Result<cpal::Stream, cpal::BuildStreamError>
 where
     f32: FromSample<T>,
 {
-    let err_fn = |err| {
-        panic!("ERROR: {err:?}");
+    let err_fn = move |err| {
+        match err {
+            StreamError::DeviceNotAvailable => log::warn!("The audio device disappeared"),
+            StreamError::BackendSpecific { err } => log::error!("Audio stream error: {err}"),
+        }
+        stream_failed.store(true, Ordering::Relaxed);
     };
 
+    let channel_count = config.channels;
     audio_device.build_input_stream(
         config,
         move |data: &[T], _: &InputCallbackInfo| {
-            for point in data {
-                let _ = tx.push(point.to_sample::<f32>());
-            }
+            // Only one stream is open at a time, so the lock is never contended
+            let Ok(mut tx) = tx.lock() else {
+                return;
+            };
+            tx.push_frames(
+                data.iter().map(|point| point.to_sample::<f32>()),
+                channel_count,
+            );
         },
         err_fn,
         None,
@@ -89,18 +533,24 @@ fn start_stream(
     config: &StreamConfig,
     audio_device: &Device,
     sample_format: &SampleFormat,
-) -> Result<(cpal::Stream, HeapConsumer<f32>), cpal::BuildStreamError> {
-    let (tx, rx) = ringbuf::HeapRb::<f32>::new(1024).split();
+    tx: Arc<Mutex<SampleProducer>>,
+    stream_failed: Arc<AtomicBool>,
+) -> Result<cpal::Stream> {
     log::info!("Starting audio stream with format: {sample_format}");
     let stream = match sample_format {
-        SampleFormat::U8 => build_audio_stream::<u8>(audio_device, config, tx),
-        SampleFormat::U16 => build_audio_stream::<u16>(audio_device, config, tx),
-        SampleFormat::I16 => build_audio_stream::<i16>(audio_device, config, tx),
-        SampleFormat::F32 => build_audio_stream::<f32>(audio_device, config, tx),
-        format => {
-            panic!("Unimplemented format: {format}");
-        }
+        SampleFormat::I8 => build_audio_stream::<i8>(audio_device, config, tx, stream_failed),
+        SampleFormat::I16 => build_audio_stream::<i16>(audio_device, config, tx, stream_failed),
+        SampleFormat::I32 => build_audio_stream::<i32>(audio_device, config, tx, stream_failed),
+        SampleFormat::I64 => build_audio_stream::<i64>(audio_device, config, tx, stream_failed),
+        SampleFormat::U8 => build_audio_stream::<u8>(audio_device, config, tx, stream_failed),
+        SampleFormat::U16 => build_audio_stream::<u16>(audio_device, config, tx, stream_failed),
+        SampleFormat::U32 => build_audio_stream::<u32>(audio_device, config, tx, stream_failed),
+        SampleFormat::U64 => build_audio_stream::<u64>(audio_device, config, tx, stream_failed),
+        SampleFormat::F32 => build_audio_stream::<f32>(audio_device, config, tx, stream_failed),
+        SampleFormat::F64 => build_audio_stream::<f64>(audio_device, config, tx, stream_failed),
+        // SampleFormat is non exhaustive
+        format => return Err(Error::UnsupportedSampleFormat(*format)),
     }?;
 
-    Ok((stream, rx))
+    Ok(stream)
 }
@@ -0,0 +1,231 @@
+use crate::audio::audio_processing::FftResult;
+use std::collections::VecDeque;
+use turbo_plugin::audio_api::{AudioChannel, OnsetBand, SpectrumView};
+
+// Number of flux values kept to compute the adaptive threshold of a band (~0.7s at 60 ticks/s)
+const FLUX_HISTORY_LENGTH: usize = 43;
+// How many standard deviations above the mean flux a tick needs to be to count as an onset
+const THRESHOLD_STD_DEVIATIONS: f32 = 1.5;
+// Flux under which we never report onsets, so that silence doesn't trigger on noise
+const MIN_FLUX: f32 = 0.01;
+// Gain applied to the magnitudes before the log compression. Makes quiet bins matter
+const LOG_COMPRESSION_GAIN: f32 = 1000.0;
+// Minimum time between two onsets in the same band
+const ONSET_COOLDOWN_SECONDS: f32 = 0.1;
+// Minimum time between two beats. Caps the detected tempo at 240 BPM
+const MIN_BEAT_INTERVAL_SECONDS: f32 = 0.25;
+// Beats further apart than this are not considered part of the same rhythm
+const MAX_BEAT_INTERVAL_SECONDS: f32 = 2.0;
+// Number of inter-beat intervals used to compute the beat confidence
+const BEAT_INTERVAL_HISTORY_LENGTH: usize = 8;
+
+#[derive(Default)]
+pub struct BeatResult {
+    is_beat: bool,
+    onsets: [bool; OnsetBand::COUNT],
+    beat_confidence: f32,
+    // Set by the `TempoTracker`
+    pub(super) beat_phase: f32,
+    pub(super) bpm: f32,
+}
+
+impl BeatResult {
+    /// Whether a beat started during the last tick.
+    pub fn is_beat(&self) -> bool {
+        self.is_beat
+    }
+
+    // Forgets the onsets and beats of the previous tick. The analyses of a tick add theirs
+    pub(super) fn clear_events(&mut self) {
+        self.is_beat = false;
+        self.onsets = [false; OnsetBand::COUNT];
+    }
+
+    /// Whether an onset was detected in `band` during the last tick.
+    pub fn is_onset(&self, band: OnsetBand) -> bool {
+        self.onsets[band as usize]
+    }
+
+    /// Position in the current beat according to the tracked tempo, in [0, 1). Crosses 0 on
+    /// every beat.
+    pub fn get_beat_phase(&self) -> f32 {
+        self.beat_phase
+    }
+
+    /// The tracked tempo, or 0 if no tempo was found yet.
+    pub fn get_bpm(&self) -> f32 {
+        self.bpm
+    }
+
+    /// How regular the recent beats were, in [0, 1]. 0 means that no rhythm was found.
+    pub fn get_beat_confidence(&self) -> f32 {
+        self.beat_confidence
+    }
+}
+
+struct BandOnsetDetector {
+    lower_frequency: f32,
+    upper_frequency: f32,
+    flux_history: VecDeque<f32>,
+    time_since_last_onset: f32,
+}
+
+impl BandOnsetDetector {
+    fn new(band: OnsetBand) -> Self {
+        let (lower_frequency, upper_frequency) = match band {
+            OnsetBand::Low => (20.0, 150.0),
+            OnsetBand::LowMid => (150.0, 800.0),
+            OnsetBand::HighMid => (800.0, 4000.0),
+            OnsetBand::High => (4000.0, 12000.0),
+        };
+        Self {
+            lower_frequency,
+            upper_frequency,
+            flux_history: VecDeque::with_capacity(FLUX_HISTORY_LENGTH),
+            time_since_last_onset: ONSET_COOLDOWN_SECONDS,
+        }
+    }
+
+    // Spectral flux: the average increase of the log-compressed bins of the band since the
+    // previous tick. Decreasing bins are ignored since they can't be the start of a sound. Both
+    // spectrums must be non-empty.
+    fn compute_flux(&self, bins: &[f32], previous_bins: &[f32], fft_resolution: f32) -> f32 {
+        let lower_index = (self.lower_frequency / fft_resolution) as usize;
+        let upper_index = ((self.upper_frequency / fft_resolution) as usize)
+            .min(bins.len().min(previous_bins.len()).saturating_sub(1));
+        if lower_index > upper_index {
+            return 0.0;
+        }
+
+        let flux = bins[lower_index..=upper_index]
+            .iter()
+            .zip(previous_bins[lower_index..=upper_index].iter())
+            .map(|(bin, previous_bin)| {
+                ((LOG_COMPRESSION_GAIN * bin).ln_1p()
+                    - (LOG_COMPRESSION_GAIN * previous_bin).ln_1p())
+                .max(0.0)
+            })
+            .sum::<f32>();
+        flux / (upper_index - lower_index + 1) as f32
+    }
+
+    fn update(&mut self, flux: f32, elapsed_seconds: f32) -> bool {
+        self.time_since_last_onset += elapsed_seconds;
+
+        let threshold = if self.flux_history.is_empty() {
+            f32::INFINITY
+        } else {
+            let count = self.flux_history.len() as f32;
+            let mean = self.flux_history.iter().sum::<f32>() / count;
+            let variance = self
+                .flux_history
+                .iter()
+                .map(|value| (value - mean).powi(2))
+                .sum::<f32>()
+                / count;
+            mean + THRESHOLD_STD_DEVIATIONS * variance.sqrt()
+        };
+
+        if self.flux_history.len() == FLUX_HISTORY_LENGTH {
+            self.flux_history.pop_front();
+        }
+        self.flux_history.push_back(flux);
+
+        let is_onset = flux > threshold
+            && flux > MIN_FLUX
+            && self.time_since_last_onset >= ONSET_COOLDOWN_SECONDS;
+        if is_onset {
+            self.time_since_last_onset = 0.0;
+        }
+        is_onset
+    }
+}
+
+pub struct BeatDetector {
+    band_detectors: Vec<BandOnsetDetector>,
+    previous_bins: Vec<f32>,
+    time_since_last_beat: f32,
+    beat_intervals: VecDeque<f32>,
+}
+
+impl BeatDetector {
+    pub fn new() -> Self {
+        Self {
+            band_detectors: OnsetBand::ALL
+                .into_iter()
+                .map(BandOnsetDetector::new)
+                .collect(),
+            previous_bins: vec![],
+            time_since_last_beat: 0.0,
+            beat_intervals: VecDeque::with_capacity(BEAT_INTERVAL_HISTORY_LENGTH),
+        }
+    }
+
+    /// Detects onsets in the mid channel of `fft_result`. `elapsed_seconds` is the duration of
+    /// audio that was received since the previous update. The onsets and beats found are added to
+    /// those of `beat_result`. Returns the onset strength of this update, summed over all bands,
+    /// and whether a beat started in this update.
+    pub fn update(
+        &mut self,
+        fft_result: &FftResult,
+        elapsed_seconds: f32,
+        beat_result: &mut BeatResult,
+    ) -> (f32, bool) {
+        let bins = fft_result.get_bins(AudioChannel::Mid, SpectrumView::Raw);
+        let fft_resolution = fft_result.get_fft_resolution();
+        let mut onset_strength = 0.0;
+        let mut onsets = [false; OnsetBand::COUNT];
+        for (band, detector) in self.band_detectors.iter_mut().enumerate() {
+            // There is no flux while there is no previous spectrum to compare to
+            let flux = if self.previous_bins.is_empty() || bins.is_empty() {
+                0.0
+            } else {
+                detector.compute_flux(bins, &self.previous_bins, fft_resolution)
+            };
+            onsets[band] = detector.update(flux, elapsed_seconds);
+            beat_result.onsets[band] |= onsets[band];
+            onset_strength += flux;
+        }
+        self.previous_bins.clear();
+        self.previous_bins.extend_from_slice(bins);
+
+        self.time_since_last_beat += elapsed_seconds;
+        if self.time_since_last_beat > MAX_BEAT_INTERVAL_SECONDS {
+            // The rhythm was lost
+            self.beat_intervals.clear();
+        }
+
+        let is_beat = onsets[OnsetBand::Low as usize]
+            && self.time_since_last_beat >= MIN_BEAT_INTERVAL_SECONDS;
+        beat_result.is_beat |= is_beat;
+        if is_beat {
+            if self.time_since_last_beat <= MAX_BEAT_INTERVAL_SECONDS {
+                if self.beat_intervals.len() == BEAT_INTERVAL_HISTORY_LENGTH {
+                    self.beat_intervals.pop_front();
+                }
+                self.beat_intervals.push_back(self.time_since_last_beat);
+            }
+            self.time_since_last_beat = 0.0;
+        }
+
+        if self.beat_intervals.is_empty() {
+            beat_result.beat_confidence = 0.0;
+            return (onset_strength, is_beat);
+        }
+
+        let count = self.beat_intervals.len() as f32;
+        let mean_interval = self.beat_intervals.iter().sum::<f32>() / count;
+        let interval_deviation = (self
+            .beat_intervals
+            .iter()
+            .map(|interval| (interval - mean_interval).powi(2))
+            .sum::<f32>()
+            / count)
+            .sqrt();
+
+        // Regular intervals give a high confidence, but only once we've heard enough beats
+        beat_result.beat_confidence = (1.0 - interval_deviation / mean_interval).clamp(0.0, 1.0)
+            * (count / BEAT_INTERVAL_HISTORY_LENGTH as f32);
+        (onset_strength, is_beat)
+    }
+}
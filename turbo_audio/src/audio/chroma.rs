@@ -0,0 +1,140 @@
+use turbo_plugin::audio_api::{KeyMode, PitchClass};
+
+// Range of the bins that count towards the chroma. Lower bins are too wide to tell the notes
+// apart, higher ones are mostly harmonics
+const MIN_CHROMA_FREQUENCY: f32 = 55.0;
+const MAX_CHROMA_FREQUENCY: f32 = 5000.0;
+// Loudest bin under which the chroma is considered silent
+const MIN_CHROMA_MAGNITUDE: f32 = 1e-4;
+// Time constant of the chroma average used to estimate the key
+const KEY_AVERAGE_SECONDS: f32 = 8.0;
+// Krumhansl-Schmuckler key profiles, starting from the tonic
+const MAJOR_PROFILE: [f32; PitchClass::COUNT] = [
+    6.35, 2.23, 3.48, 2.33, 4.38, 4.09, 2.52, 5.19, 2.39, 3.66, 2.29, 2.88,
+];
+const MINOR_PROFILE: [f32; PitchClass::COUNT] = [
+    6.33, 2.68, 3.52, 5.38, 2.60, 3.53, 2.54, 4.75, 3.98, 2.69, 3.34, 3.17,
+];
+
+pub type Chroma = [f32; PitchClass::COUNT];
+
+/// Folds the spectrum into the 12 pitch classes.
+pub struct ChromaExtractor {
+    // Pitch class of every bin, if it is in the chroma range
+    bin_pitch_classes: Vec<Option<usize>>,
+}
+
+impl ChromaExtractor {
+    pub fn new(bin_count: usize, fft_resolution: f32) -> Self {
+        Self {
+            bin_pitch_classes: (0..bin_count)
+                .map(|index| {
+                    let frequency = index as f32 * fft_resolution;
+                    if !(MIN_CHROMA_FREQUENCY..=MAX_CHROMA_FREQUENCY).contains(&frequency) {
+                        return None;
+                    }
+                    // MIDI note numbers put C on multiples of 12
+                    let note = 69.0 + 12.0 * (frequency / 440.0).log2();
+                    Some((note.round() as usize) % PitchClass::COUNT)
+                })
+                .collect(),
+        }
+    }
+
+    /// Sums the energy of the bins of every pitch class, relative to the strongest one. All zeros
+    /// on silence.
+    pub fn compute(&self, bins: &[f32]) -> Chroma {
+        let mut chroma = [0.0; PitchClass::COUNT];
+        let mut max_magnitude = 0.0f32;
+        for (bin, pitch_class) in bins.iter().zip(self.bin_pitch_classes.iter()) {
+            if let Some(pitch_class) = pitch_class {
+                chroma[*pitch_class] += bin * bin;
+                max_magnitude = max_magnitude.max(*bin);
+            }
+        }
+
+        if max_magnitude < MIN_CHROMA_MAGNITUDE {
+            return [0.0; PitchClass::COUNT];
+        }
+        let max_energy = chroma.iter().fold(0.0f32, |max, energy| max.max(*energy));
+        chroma.iter_mut().for_each(|energy| *energy /= max_energy);
+        chroma
+    }
+}
+
+/// The pitch class with the most energy, or `None` on silence.
+pub fn dominant_pitch(chroma: &Chroma) -> Option<PitchClass> {
+    let (index, energy) = chroma
+        .iter()
+        .enumerate()
+        .max_by(|(_, a), (_, b)| a.total_cmp(b))?;
+    (*energy > 0.0).then_some(PitchClass::ALL[index])
+}
+
+/// Estimates the key by matching the recent chroma against the profile of every key.
+pub struct KeyTracker {
+    average_chroma: Chroma,
+}
+
+impl KeyTracker {
+    pub fn new() -> Self {
+        Self {
+            average_chroma: [0.0; PitchClass::COUNT],
+        }
+    }
+
+    /// `elapsed_seconds` is the duration of audio covered by `chroma`. Silent chromas are ignored
+    /// so that pauses don't reset the key.
+    pub fn update(
+        &mut self,
+        chroma: &Chroma,
+        elapsed_seconds: f32,
+    ) -> Option<(PitchClass, KeyMode)> {
+        if dominant_pitch(chroma).is_some() {
+            let factor = 1.0 - (-elapsed_seconds / KEY_AVERAGE_SECONDS).exp();
+            for (average, energy) in self.average_chroma.iter_mut().zip(chroma.iter()) {
+                *average += (energy - *average) * factor;
+            }
+        }
+
+        if self.average_chroma.iter().all(|energy| *energy <= 0.0) {
+            return None;
+        }
+
+        PitchClass::ALL
+            .into_iter()
+            .enumerate()
+            .flat_map(|(tonic, pitch_class)| {
+                [
+                    (pitch_class, KeyMode::Major, &MAJOR_PROFILE),
+                    (pitch_class, KeyMode::Minor, &MINOR_PROFILE),
+                ]
+                .map(|(pitch_class, mode, profile)| {
+                    (pitch_class, mode, self.correlation(profile, tonic))
+                })
+            })
+            .max_by(|(_, _, a), (_, _, b)| a.total_cmp(b))
+            .map(|(pitch_class, mode, _)| (pitch_class, mode))
+    }
+
+    // Pearson correlation between the average chroma and `profile` moved to start on `tonic`
+    fn correlation(&self, profile: &Chroma, tonic: usize) -> f32 {
+        let count = PitchClass::COUNT as f32;
+        let chroma_mean = self.average_chroma.iter().sum::<f32>() / count;
+        let profile_mean = profile.iter().sum::<f32>() / count;
+        let (mut covariance, mut chroma_variance, mut profile_variance) = (0.0, 0.0, 0.0);
+        for (pitch_class, energy) in self.average_chroma.iter().enumerate() {
+            let chroma_deviation = energy - chroma_mean;
+            let profile_deviation = profile
+                [(pitch_class + PitchClass::COUNT - tonic) % PitchClass::COUNT]
+                - profile_mean;
+            covariance += chroma_deviation * profile_deviation;
+            chroma_variance += chroma_deviation * chroma_deviation;
+            profile_variance += profile_deviation * profile_deviation;
+        }
+        covariance
+            / (chroma_variance * profile_variance)
+                .sqrt()
+                .max(f32::EPSILON)
+    }
+}
@@ -0,0 +1,252 @@
+use crate::audio::sample_buffer::{sample_buffer, SampleConsumer, SampleProducer};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::File,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+use symphonia::core::{
+    audio::SampleBuffer,
+    codecs::{Decoder, DecoderOptions},
+    errors::Error as SymphoniaError,
+    formats::{FormatOptions, FormatReader},
+    io::MediaSourceStream,
+    meta::MetadataOptions,
+    probe::Hint,
+};
+
+// Seconds of audio that the decoder can get ahead of the processor
+const BUFFER_SECONDS: u32 = 1;
+// How long the decoder waits before retrying when the buffer is full
+const FULL_BUFFER_WAIT: Duration = Duration::from_millis(1);
+
+pub(super) fn default_speed() -> f32 {
+    1.0
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileSourceConfig {
+    /// WAV, FLAC or OGG Vorbis file to play.
+    pub path: PathBuf,
+    /// Playback speed relative to real time. 0 decodes as fast as the samples are consumed.
+    #[serde(default = "default_speed")]
+    pub speed: f32,
+    /// Start over at the end of the file instead of going silent.
+    #[serde(default)]
+    pub looping: bool,
+}
+
+/// Decodes an audio file on a separate thread and pushes its interleaved samples at a steady pace,
+/// like a capture device would.
+pub struct FileSource {
+    should_stop: Arc<AtomicBool>,
+    is_finished: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for FileSource {
+    fn drop(&mut self) {
+        self.should_stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl FileSource {
+    /// Starts playing the file. Returns the consumer of the decoded samples along with their
+    /// channel count and sample rate.
+    pub fn start(config: &FileSourceConfig) -> Result<(Self, SampleConsumer, u16, u32)> {
+        let (format, decoder) = open_file(&config.path)?;
+        let codec_params = decoder.codec_params();
+        let sample_rate = codec_params
+            .sample_rate
+            .context("The audio file has no sample rate")?;
+        let channel_count = codec_params
+            .channels
+            .context("The audio file has no channel layout")?
+            .count() as u16;
+        log::info!(
+            "Playing {} ({channel_count} channels at {sample_rate} Hz)",
+            config.path.display()
+        );
+
+        let (tx, rx) =
+            sample_buffer((sample_rate * BUFFER_SECONDS) as usize * channel_count as usize);
+        let should_stop = Arc::new(AtomicBool::new(false));
+        let is_finished = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let config = config.clone();
+            let should_stop = should_stop.clone();
+            let is_finished = is_finished.clone();
+            std::thread::spawn(move || {
+                let mut playback = Playback::new(
+                    tx,
+                    should_stop,
+                    config.speed,
+                    sample_rate as u64 * channel_count as u64,
+                );
+                let mut file = Some((format, decoder));
+                while let Some((format, decoder)) = file.take() {
+                    if let Err(e) = playback.play(format, decoder) {
+                        log::error!("Error while decoding {}: {e:?}", config.path.display());
+                        break;
+                    }
+                    if !config.looping || playback.should_stop.load(Ordering::Relaxed) {
+                        break;
+                    }
+                    file = open_file(&config.path)
+                        .map_err(|e| log::error!("Couldn't reopen the audio file: {e:?}"))
+                        .ok();
+                }
+                is_finished.store(true, Ordering::Relaxed);
+            })
+        };
+
+        Ok((
+            Self {
+                should_stop,
+                is_finished,
+                thread: Some(thread),
+            },
+            rx,
+            channel_count,
+            sample_rate,
+        ))
+    }
+
+    /// Whether every sample of the file was pushed. Never true when looping.
+    pub fn is_finished(&self) -> bool {
+        self.is_finished.load(Ordering::Relaxed)
+    }
+}
+
+/// Pushes samples at the pace of a real device, or as fast as they are consumed at speed 0.
+pub(super) struct Playback {
+    tx: SampleProducer,
+    should_stop: Arc<AtomicBool>,
+    speed: f32,
+    // All channels included
+    samples_per_second: u64,
+    start_time: Instant,
+    pushed_samples: u64,
+}
+
+impl Playback {
+    pub(super) fn new(
+        tx: SampleProducer,
+        should_stop: Arc<AtomicBool>,
+        speed: f32,
+        samples_per_second: u64,
+    ) -> Self {
+        Self {
+            tx,
+            should_stop,
+            speed,
+            samples_per_second,
+            start_time: Instant::now(),
+            pushed_samples: 0,
+        }
+    }
+
+    fn play(
+        &mut self,
+        mut format: Box<dyn FormatReader>,
+        mut decoder: Box<dyn Decoder>,
+    ) -> Result<()> {
+        let track_id = format
+            .default_track()
+            .context("The audio file has no audio track")?
+            .id;
+        let mut sample_buffer = None;
+        loop {
+            let packet = match format.next_packet() {
+                Ok(packet) => packet,
+                Err(SymphoniaError::IoError(e))
+                    if e.kind() == std::io::ErrorKind::UnexpectedEof =>
+                {
+                    return Ok(());
+                }
+                Err(e) => return Err(e.into()),
+            };
+            if packet.track_id() != track_id {
+                continue;
+            }
+
+            let decoded = match decoder.decode(&packet) {
+                Ok(decoded) => decoded,
+                Err(SymphoniaError::DecodeError(e)) => {
+                    log::warn!("Skipping undecodable packet: {e}");
+                    continue;
+                }
+                Err(e) => return Err(e.into()),
+            };
+            let sample_buffer = sample_buffer.get_or_insert_with(|| {
+                SampleBuffer::<f32>::new(decoded.capacity() as u64, *decoded.spec())
+            });
+            sample_buffer.copy_interleaved_ref(decoded);
+            if !self.push(sample_buffer.samples()) {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Pushes the samples once the playback caught up with them. Returns false if playback was
+    /// stopped in the meantime.
+    pub(super) fn push(&mut self, mut samples: &[f32]) -> bool {
+        if self.speed > 0.0 {
+            // In f64 so that the pace stays exact for hours of audio
+            let due_time = Duration::from_secs_f64(
+                self.pushed_samples as f64 / self.samples_per_second as f64 / self.speed as f64,
+            );
+            if let Some(wait) = due_time.checked_sub(self.start_time.elapsed()) {
+                std::thread::sleep(wait);
+            }
+        }
+
+        while !samples.is_empty() {
+            if self.should_stop.load(Ordering::Relaxed) {
+                return false;
+            }
+            let pushed = self.tx.push_slice(samples);
+            samples = &samples[pushed..];
+            self.pushed_samples += pushed as u64;
+            if samples.is_empty() {
+                break;
+            }
+            std::thread::sleep(FULL_BUFFER_WAIT);
+        }
+        true
+    }
+}
+
+fn open_file(path: &Path) -> Result<(Box<dyn FormatReader>, Box<dyn Decoder>)> {
+    let file = File::open(path).with_context(|| format!("Couldn't open {}", path.display()))?;
+    let mut hint = Hint::new();
+    if let Some(extension) = path.extension().and_then(|extension| extension.to_str()) {
+        hint.with_extension(extension);
+    }
+
+    let format = symphonia::default::get_probe()
+        .format(
+            &hint,
+            MediaSourceStream::new(Box::new(file), Default::default()),
+            &FormatOptions::default(),
+            &MetadataOptions::default(),
+        )
+        .with_context(|| format!("Unsupported audio file: {}", path.display()))?
+        .format;
+    let track = format
+        .default_track()
+        .context("The audio file has no audio track")?;
+    let decoder = symphonia::default::get_codecs()
+        .make(&track.codec_params, &DecoderOptions::default())
+        .context("Unsupported audio codec")?;
+    Ok((format, decoder))
+}
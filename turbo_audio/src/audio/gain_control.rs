@@ -0,0 +1,113 @@
+use serde::{Deserialize, Serialize};
+use turbo_plugin::audio_api::AudioChannel;
+
+// Lowest frequency covered by the first gain control band. Bins under it follow the first band
+const MIN_BAND_FREQUENCY: f32 = 20.0;
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AgcConfig {
+    /// Time for the tracked level to rise to a louder signal.
+    pub attack_seconds: f32,
+    /// Time for the tracked level to fall back after the signal got quieter.
+    pub release_seconds: f32,
+    /// Number of log-spaced bands that are normalized independently.
+    pub band_count: usize,
+    /// Level under which the signal is considered silent and isn't amplified further.
+    pub noise_floor: f32,
+}
+
+impl Default for AgcConfig {
+    fn default() -> Self {
+        Self {
+            attack_seconds: 0.01,
+            release_seconds: 2.0,
+            band_count: 8,
+            noise_floor: 1e-4,
+        }
+    }
+}
+
+/// Follows the loudness of every band of the spectrum, so that it can be rescaled to [0, 1]
+/// whatever the playback volume.
+pub struct AutomaticGainControl {
+    config: AgcConfig,
+    // Gain control band of every bin
+    bin_bands: Vec<usize>,
+    // Tracked level of every band, per channel
+    levels: [Vec<f32>; AudioChannel::COUNT],
+}
+
+impl AutomaticGainControl {
+    pub fn new(config: AgcConfig, bin_count: usize, fft_resolution: f32) -> Self {
+        let band_count = config.band_count.max(1);
+        let bin_bands = log_bin_bands(bin_count, fft_resolution, band_count);
+        Self {
+            levels: std::array::from_fn(|_| vec![config.noise_floor; band_count]),
+            config,
+            bin_bands,
+        }
+    }
+
+    /// Updates the tracked levels of `channel` with `bins`, then writes the bins divided by the
+    /// level of their band to `normalized_bins`. `elapsed_seconds` is the duration of audio since
+    /// the previous update.
+    pub fn process(
+        &mut self,
+        channel: AudioChannel,
+        bins: &[f32],
+        normalized_bins: &mut Vec<f32>,
+        elapsed_seconds: f32,
+    ) {
+        let levels = &mut self.levels[channel as usize];
+
+        // Peak of each band for this update. Peaks rather than RMS so that the loudest bin of a
+        // band reaches 1
+        let mut peaks = vec![0.0f32; levels.len()];
+        for (bin, band) in bins.iter().zip(self.bin_bands.iter()) {
+            peaks[*band] = peaks[*band].max(*bin);
+        }
+
+        let attack = smoothing_factor(elapsed_seconds, self.config.attack_seconds);
+        let release = smoothing_factor(elapsed_seconds, self.config.release_seconds);
+        for (level, peak) in levels.iter_mut().zip(peaks) {
+            let factor = if peak > *level { attack } else { release };
+            *level = (*level + (peak - *level) * factor).max(self.config.noise_floor);
+        }
+
+        normalized_bins.clear();
+        normalized_bins.extend(
+            bins.iter()
+                .zip(self.bin_bands.iter())
+                .map(|(bin, band)| (bin / levels[*band]).min(1.0)),
+        );
+    }
+}
+
+/// Assigns every bin to one of `band_count` bands covering the same number of octaves.
+pub(super) fn log_bin_bands(
+    bin_count: usize,
+    fft_resolution: f32,
+    band_count: usize,
+) -> Vec<usize> {
+    let max_frequency = (bin_count - 1) as f32 * fft_resolution;
+    let octave_count = (max_frequency / MIN_BAND_FREQUENCY)
+        .log2()
+        .max(f32::EPSILON);
+    (0..bin_count)
+        .map(|index| {
+            let frequency = (index as f32 * fft_resolution).max(MIN_BAND_FREQUENCY);
+            let position = (frequency / MIN_BAND_FREQUENCY).log2() / octave_count;
+            ((position * band_count as f32) as usize).min(band_count - 1)
+        })
+        .collect()
+}
+
+// How much of the distance to the target a one pole filter with the `time_constant` covers in
+// `elapsed_seconds`
+pub(super) fn smoothing_factor(elapsed_seconds: f32, time_constant: f32) -> f32 {
+    if time_constant <= 0.0 {
+        return 1.0;
+    }
+    1.0 - (-elapsed_seconds / time_constant).exp()
+}
@@ -0,0 +1,223 @@
+use crate::audio::{
+    file_source::{default_speed, Playback},
+    sample_buffer::{sample_buffer, SampleConsumer},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    f32::consts::TAU,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::JoinHandle,
+};
+
+// Seconds of audio that the generator can get ahead of the processor
+const BUFFER_SECONDS: u32 = 1;
+// Number of blocks generated per second of audio
+const BLOCKS_PER_SECOND: u32 = 100;
+// Frequency and duration of the clicks of click tracks
+const CLICK_FREQUENCY: f32 = 1000.0;
+const CLICK_SECONDS: f32 = 0.02;
+
+fn default_amplitude() -> f32 {
+    0.5
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum GeneratorSignal {
+    /// Logarithmic sweep from `start_frequency` to `end_frequency`, starting over every
+    /// `duration_seconds`.
+    Sweep {
+        start_frequency: f32,
+        end_frequency: f32,
+        duration_seconds: f32,
+    },
+    WhiteNoise,
+    /// Noise with equal energy per octave.
+    PinkNoise,
+    /// A short click on every beat.
+    Clicks {
+        bpm: f32,
+    },
+    /// Sines at every frequency, played together.
+    Chord {
+        frequencies: Vec<f32>,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GeneratorSourceConfig {
+    pub signal: GeneratorSignal,
+    /// Peak amplitude, 1 being full scale.
+    #[serde(default = "default_amplitude")]
+    pub amplitude: f32,
+    /// Seed of the noise, so that runs can be reproduced.
+    #[serde(default)]
+    pub seed: u64,
+    /// Playback speed relative to real time. 0 generates as fast as the samples are consumed.
+    #[serde(default = "default_speed")]
+    pub speed: f32,
+}
+
+/// Generates a test signal on a separate thread, and pushes it on every channel at a steady pace,
+/// like a capture device would.
+pub struct GeneratorSource {
+    should_stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for GeneratorSource {
+    fn drop(&mut self) {
+        self.should_stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl GeneratorSource {
+    pub fn start(
+        config: &GeneratorSourceConfig,
+        channel_count: u16,
+        sample_rate: u32,
+    ) -> (Self, SampleConsumer) {
+        log::info!(
+            "Generating {:?} ({channel_count} channels at {sample_rate} Hz)",
+            config.signal
+        );
+        let channel_count = channel_count.max(1) as usize;
+        let (tx, rx) = sample_buffer((sample_rate * BUFFER_SECONDS) as usize * channel_count);
+        let should_stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let config = config.clone();
+            let should_stop = should_stop.clone();
+            std::thread::spawn(move || {
+                let mut playback = Playback::new(
+                    tx,
+                    should_stop,
+                    config.speed,
+                    sample_rate as u64 * channel_count as u64,
+                );
+                let mut generator = Generator::new(&config, sample_rate);
+                let frames_per_block = (sample_rate / BLOCKS_PER_SECOND).max(1) as usize;
+                let mut block = vec![0.0; frames_per_block * channel_count];
+                loop {
+                    for frame in block.chunks_exact_mut(channel_count) {
+                        frame.fill(generator.next_sample());
+                    }
+                    if !playback.push(&block) {
+                        break;
+                    }
+                }
+            })
+        };
+
+        (
+            Self {
+                should_stop,
+                thread: Some(thread),
+            },
+            rx,
+        )
+    }
+}
+
+struct Generator {
+    signal: GeneratorSignal,
+    amplitude: f32,
+    sample_rate: f32,
+    // Index of the next sample
+    sample_index: u64,
+    // Phase of every oscillator, in turns
+    phases: Vec<f32>,
+    // State of the xorshift noise generator. Never 0
+    noise_state: u64,
+    // State of the pink noise filter
+    pink_filter: [f32; 7],
+}
+
+impl Generator {
+    fn new(config: &GeneratorSourceConfig, sample_rate: u32) -> Self {
+        let oscillator_count = match &config.signal {
+            GeneratorSignal::Chord { frequencies } => frequencies.len(),
+            _ => 1,
+        };
+        Self {
+            signal: config.signal.clone(),
+            amplitude: config.amplitude,
+            sample_rate: sample_rate as f32,
+            sample_index: 0,
+            phases: vec![0.0; oscillator_count],
+            noise_state: (config.seed ^ 0x9E37_79B9_7F4A_7C15).max(1),
+            pink_filter: [0.0; 7],
+        }
+    }
+
+    fn next_sample(&mut self) -> f32 {
+        // In f64, since an f32 time loses too much precision after about an hour
+        let time = self.sample_index as f64 / self.sample_rate as f64;
+        self.sample_index += 1;
+
+        let sample = match &self.signal {
+            GeneratorSignal::Sweep {
+                start_frequency,
+                end_frequency,
+                duration_seconds,
+            } => {
+                let progress = (time / duration_seconds.max(f32::EPSILON) as f64).fract() as f32;
+                let frequency = start_frequency * (end_frequency / start_frequency).powf(progress);
+                oscillate(&mut self.phases[0], frequency, self.sample_rate)
+            }
+            GeneratorSignal::WhiteNoise => self.white_noise(),
+            GeneratorSignal::PinkNoise => {
+                // Paul Kellet's filter, scaled back to about [-1, 1]
+                let white = self.white_noise();
+                let b = &mut self.pink_filter;
+                b[0] = 0.99886 * b[0] + white * 0.0555179;
+                b[1] = 0.99332 * b[1] + white * 0.0750759;
+                b[2] = 0.96900 * b[2] + white * 0.153852;
+                b[3] = 0.86650 * b[3] + white * 0.3104856;
+                b[4] = 0.55000 * b[4] + white * 0.5329522;
+                b[5] = -0.7616 * b[5] - white * 0.0168980;
+                let pink = b[0] + b[1] + b[2] + b[3] + b[4] + b[5] + b[6] + white * 0.5362;
+                b[6] = white * 0.115926;
+                pink * 0.2
+            }
+            GeneratorSignal::Clicks { bpm } => {
+                let beat_seconds = 60.0 / bpm.max(f32::EPSILON) as f64;
+                let since_beat = (time % beat_seconds) as f32;
+                if since_beat < CLICK_SECONDS {
+                    (TAU * CLICK_FREQUENCY * since_beat).sin() * (1.0 - since_beat / CLICK_SECONDS)
+                } else {
+                    0.0
+                }
+            }
+            GeneratorSignal::Chord { frequencies } => {
+                let sum: f32 = frequencies
+                    .iter()
+                    .zip(self.phases.iter_mut())
+                    .map(|(frequency, phase)| oscillate(phase, *frequency, self.sample_rate))
+                    .sum();
+                sum / frequencies.len().max(1) as f32
+            }
+        };
+        sample * self.amplitude
+    }
+
+    // Uniform in [-1, 1]
+    fn white_noise(&mut self) -> f32 {
+        self.noise_state ^= self.noise_state << 13;
+        self.noise_state ^= self.noise_state >> 7;
+        self.noise_state ^= self.noise_state << 17;
+        (self.noise_state >> 40) as f32 / (1u64 << 23) as f32 - 1.0
+    }
+}
+
+// Advances an oscillator by one sample. Accumulating the phase keeps the sine continuous when the
+// frequency changes
+fn oscillate(phase: &mut f32, frequency: f32, sample_rate: f32) -> f32 {
+    let sample = (TAU * *phase).sin();
+    *phase = (*phase + frequency / sample_rate).fract();
+    sample
+}
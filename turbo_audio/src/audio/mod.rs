@@ -1,3 +1,15 @@
+pub mod audio_input;
 pub mod audio_processing;
+pub mod audio_source;
 pub mod audio_stream;
+pub mod beat_detection;
+pub mod chroma;
+pub mod file_source;
+pub mod gain_control;
+pub mod generator_source;
 pub mod pipewire_listener;
+pub mod pipewire_source;
+pub mod sample_buffer;
+pub mod signal_features;
+pub mod spectrum_smoothing;
+pub mod tempo_tracking;
@@ -0,0 +1,183 @@
+use crate::audio::sample_buffer::{sample_buffer, tick_capacity, SampleConsumer, SampleProducer};
+use anyhow::{anyhow, Context, Result};
+use pipewire::{
+    properties,
+    spa::{self, pod::Pod},
+    stream::{Stream, StreamFlags},
+    MainLoop,
+};
+use serde::{Deserialize, Serialize};
+use std::{sync::mpsc, thread::JoinHandle};
+
+fn default_node_name() -> String {
+    "turbo_audio".to_owned()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PipewireSourceConfig {
+    /// Name of the capture node. Use it as the `input_stream` of `stream_connections`.
+    #[serde(default = "default_node_name")]
+    pub node_name: String,
+}
+
+/// Creates a pipewire capture node and pushes what is routed to it, as interleaved f32 samples at
+/// the requested channel count and sample rate. Pipewire converts whatever it receives to that
+/// format.
+pub struct PipewireSource {
+    quit_sender: pipewire::channel::Sender<()>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for PipewireSource {
+    fn drop(&mut self) {
+        let _ = self.quit_sender.send(());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl PipewireSource {
+    pub fn start(
+        config: &PipewireSourceConfig,
+        channel_count: u16,
+        sample_rate: u32,
+    ) -> Result<(Self, SampleConsumer)> {
+        let (tx, rx) = sample_buffer(tick_capacity(
+            sample_rate,
+            channel_count,
+            crate::TICKS_PER_SECOND,
+        ));
+        let (quit_sender, quit_receiver) = pipewire::channel::channel();
+        let (started_tx, started_rx) = mpsc::channel();
+        // Pipewire objects can't be sent between threads, so the stream lives on the thread of its
+        // main loop
+        let thread = {
+            let node_name = config.node_name.clone();
+            std::thread::spawn(move || {
+                if let Err(e) = capture_thread(
+                    &node_name,
+                    channel_count,
+                    sample_rate,
+                    tx,
+                    quit_receiver,
+                    &started_tx,
+                ) {
+                    let _ = started_tx.send(Err(e));
+                }
+            })
+        };
+        started_rx
+            .recv()
+            .context("The pipewire capture thread stopped unexpectedly")??;
+        log::info!(
+            "Capturing audio from the pipewire node {} ({channel_count} channels at {sample_rate} \
+             Hz)",
+            config.node_name
+        );
+
+        Ok((
+            Self {
+                quit_sender,
+                thread: Some(thread),
+            },
+            rx,
+        ))
+    }
+}
+
+fn capture_thread(
+    node_name: &str,
+    channel_count: u16,
+    sample_rate: u32,
+    tx: SampleProducer,
+    quit_receiver: pipewire::channel::Receiver<()>,
+    started: &mpsc::Sender<Result<()>>,
+) -> Result<()> {
+    let mainloop = MainLoop::new().context("Couldn't create pipewire mainloop")?;
+    let _quit_receiver = quit_receiver.attach(&mainloop, {
+        let mainloop = mainloop.clone();
+        move |_| mainloop.quit()
+    });
+    let context = pipewire::Context::new(&mainloop).context("Couldn't create pipewire context")?;
+    let core = context
+        .connect(None)
+        .context("Couldn't create pipewire core")?;
+
+    // The node is named the same everywhere, since the pipewire listener matches nodes on their
+    // nick, description or name
+    let stream = Stream::new(
+        &core,
+        node_name,
+        properties! {
+            *pipewire::keys::MEDIA_TYPE => "Audio",
+            *pipewire::keys::MEDIA_CATEGORY => "Capture",
+            *pipewire::keys::MEDIA_ROLE => "Music",
+            *pipewire::keys::NODE_NAME => node_name,
+            *pipewire::keys::NODE_NICK => node_name,
+            *pipewire::keys::NODE_DESCRIPTION => node_name,
+        },
+    )
+    .context("Couldn't create the pipewire stream")?;
+    // Kept until the main loop quits, like the core
+    let _listener = stream
+        .add_local_listener_with_user_data(tx)
+        .process(move |stream, tx| {
+            let Some(mut buffer) = stream.dequeue_buffer() else {
+                return;
+            };
+            let Some(data) = buffer.datas_mut().first_mut() else {
+                return;
+            };
+            let offset = data.chunk().offset() as usize;
+            let size = data.chunk().size() as usize;
+            let Some(bytes) = data.data() else {
+                return;
+            };
+            let end = (offset + size).min(bytes.len());
+            let samples = bytes[offset.min(end)..end]
+                .chunks_exact(4)
+                .map(|sample| f32::from_le_bytes([sample[0], sample[1], sample[2], sample[3]]));
+            tx.push_frames(samples, channel_count);
+        })
+        .register()
+        .context("Couldn't listen to the pipewire stream")?;
+
+    let mut audio_info = spa::param::audio::AudioInfoRaw::new();
+    audio_info.set_format(spa::param::audio::AudioFormat::F32LE);
+    audio_info.set_rate(sample_rate);
+    audio_info.set_channels(channel_count as u32);
+    if channel_count == 2 {
+        // Name the ports input_FL and input_FR, like the ALSA plug-in
+        let mut position = [0; spa::param::audio::MAX_CHANNELS];
+        position[0] = spa::sys::SPA_AUDIO_CHANNEL_FL;
+        position[1] = spa::sys::SPA_AUDIO_CHANNEL_FR;
+        audio_info.set_position(position);
+    }
+    let format = spa::pod::serialize::PodSerializer::serialize(
+        std::io::Cursor::new(Vec::new()),
+        &spa::pod::Value::Object(spa::pod::Object {
+            type_: spa::utils::SpaTypes::ObjectParamFormat.as_raw(),
+            id: spa::param::ParamType::EnumFormat.as_raw(),
+            properties: audio_info.into(),
+        }),
+    )
+    .map_err(|e| anyhow!("Couldn't serialize the audio format: {e:?}"))?
+    .0
+    .into_inner();
+    let mut params = [Pod::from_bytes(&format).context("Invalid audio format")?];
+
+    // Not autoconnected: the node only receives what `stream_connections` routes to it
+    stream
+        .connect(
+            spa::Direction::Input,
+            None,
+            StreamFlags::MAP_BUFFERS | StreamFlags::RT_PROCESS,
+            &mut params,
+        )
+        .context("Couldn't connect the pipewire stream")?;
+
+    let _ = started.send(Ok(()));
+    mainloop.run();
+    Ok(())
+}
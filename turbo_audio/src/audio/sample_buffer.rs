@@ -0,0 +1,114 @@
+use ringbuf::{HeapConsumer, HeapProducer};
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+// Number of ticks worth of samples that can wait in the buffer before new ones get dropped
+const BUFFERED_TICKS: u32 = 8;
+
+/// Counters describing how well the analysis keeps up with the audio source.
+#[derive(Default)]
+pub struct SampleBufferStats {
+    overruns: AtomicU64,
+    underruns: AtomicU64,
+    skipped: AtomicU64,
+}
+
+impl SampleBufferStats {
+    /// Frames dropped by the source because the buffer was full.
+    pub fn overruns(&self) -> u64 {
+        self.overruns.load(Ordering::Relaxed)
+    }
+
+    /// Ticks during which no sample was available.
+    pub fn underruns(&self) -> u64 {
+        self.underruns.load(Ordering::Relaxed)
+    }
+
+    /// Samples left unanalyzed to catch up with the newest ones.
+    pub fn skipped(&self) -> u64 {
+        self.skipped.load(Ordering::Relaxed)
+    }
+}
+
+/// Capacity holding a few ticks worth of interleaved samples.
+pub fn tick_capacity(sample_rate: u32, channel_count: u16, ticks_per_second: u32) -> usize {
+    let frames_per_tick = sample_rate.div_ceil(ticks_per_second.max(1));
+    (frames_per_tick * BUFFERED_TICKS) as usize * channel_count.max(1) as usize
+}
+
+/// Creates a buffer of interleaved samples shared by the audio source and the processor.
+pub fn sample_buffer(capacity: usize) -> (SampleProducer, SampleConsumer) {
+    let (tx, rx) = ringbuf::HeapRb::<f32>::new(capacity).split();
+    let stats = Arc::new(SampleBufferStats::default());
+    (
+        SampleProducer {
+            tx,
+            stats: stats.clone(),
+        },
+        SampleConsumer { rx, stats },
+    )
+}
+
+pub struct SampleProducer {
+    tx: HeapProducer<f32>,
+    stats: Arc<SampleBufferStats>,
+}
+
+impl SampleProducer {
+    /// Pushes the interleaved frames that fit and counts the others as overruns. Only whole frames
+    /// are dropped, so a full buffer never shifts the channels.
+    pub fn push_frames(&mut self, samples: impl ExactSizeIterator<Item = f32>, channel_count: u16) {
+        let channel_count = channel_count.max(1) as usize;
+        let frame_count = samples.len() / channel_count;
+        let pushed_frames = frame_count.min(self.tx.free_len() / channel_count);
+        self.tx
+            .push_iter(&mut samples.take(pushed_frames * channel_count));
+
+        let dropped_frames = frame_count - pushed_frames;
+        if dropped_frames > 0 {
+            self.stats
+                .overruns
+                .fetch_add(dropped_frames as u64, Ordering::Relaxed);
+        }
+    }
+
+    /// Pushes as many samples as fit and returns their count. For sources that wait for room
+    /// instead of dropping samples, so nothing is counted as an overrun.
+    pub fn push_slice(&mut self, samples: &[f32]) -> usize {
+        self.tx.push_slice(samples)
+    }
+}
+
+pub struct SampleConsumer {
+    rx: HeapConsumer<f32>,
+    stats: Arc<SampleBufferStats>,
+}
+
+impl SampleConsumer {
+    pub fn pop_slice(&mut self, samples: &mut [f32]) -> usize {
+        self.rx.pop_slice(samples)
+    }
+
+    /// Drops the `count` oldest samples.
+    pub fn skip(&mut self, count: usize) -> usize {
+        let skipped = self.rx.skip(count);
+        self.stats
+            .skipped
+            .fetch_add(skipped as u64, Ordering::Relaxed);
+        skipped
+    }
+
+    pub fn count_underrun(&self) {
+        self.stats.underruns.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn len(&self) -> usize {
+        self.rx.len()
+    }
+
+    pub fn stats(&self) -> &SampleBufferStats {
+        &self.stats
+    }
+}
@@ -0,0 +1,106 @@
+use turbo_plugin::audio_api::{AudioChannel, SignalFeature};
+
+// Share of the spectral energy under the rolloff frequency
+const ROLLOFF_ENERGY_RATIO: f32 = 0.85;
+// Added to the powers before taking their log, so that silent bins don't make the flatness 0
+const FLATNESS_EPSILON: f32 = 1e-12;
+
+/// Time-domain and spectral descriptors of the latest analysis window, per channel.
+#[derive(Default)]
+pub struct SignalFeatures {
+    features: [[f32; SignalFeature::COUNT]; AudioChannel::COUNT],
+    waveforms: [Vec<f32>; AudioChannel::COUNT],
+}
+
+impl SignalFeatures {
+    pub fn get_feature(&self, feature: SignalFeature, channel: AudioChannel) -> f32 {
+        self.features[channel as usize][feature as usize]
+    }
+
+    /// Fills `waveform` with the latest analysis window of `channel`, linearly resampled to
+    /// `waveform.len()` points.
+    pub fn get_waveform(&self, waveform: &mut [f32], channel: AudioChannel) {
+        let samples = &self.waveforms[channel as usize];
+        if samples.is_empty() {
+            waveform.fill(0.0);
+            return;
+        }
+
+        let step = if waveform.len() > 1 {
+            (samples.len() - 1) as f32 / (waveform.len() - 1) as f32
+        } else {
+            0.0
+        };
+        for (index, point) in waveform.iter_mut().enumerate() {
+            let position = index as f32 * step;
+            let lower_index = position as usize;
+            let upper_index = (lower_index + 1).min(samples.len() - 1);
+            *point = samples[lower_index]
+                + (position - lower_index as f32) * (samples[upper_index] - samples[lower_index]);
+        }
+    }
+
+    /// Recomputes the features of `channel` from the samples of the analysis window and the
+    /// linear magnitudes of their spectrum.
+    pub(super) fn update(
+        &mut self,
+        channel: AudioChannel,
+        samples: &[f32],
+        bins: &[f32],
+        fft_resolution: f32,
+    ) {
+        let features = &mut self.features[channel as usize];
+        features.fill(0.0);
+
+        let waveform = &mut self.waveforms[channel as usize];
+        waveform.clear();
+        waveform.extend_from_slice(samples);
+
+        if !samples.is_empty() {
+            let rms = (samples.iter().map(|x| x * x).sum::<f32>() / samples.len() as f32).sqrt();
+            let peak = samples.iter().fold(0.0f32, |peak, x| peak.max(x.abs()));
+            let zero_crossings = samples
+                .windows(2)
+                .filter(|pair| (pair[0] >= 0.0) != (pair[1] >= 0.0))
+                .count();
+            features[SignalFeature::Rms as usize] = rms;
+            features[SignalFeature::Peak as usize] = peak;
+            features[SignalFeature::CrestFactor as usize] =
+                if rms > 0.0 { peak / rms } else { 0.0 };
+            features[SignalFeature::ZeroCrossingRate as usize] =
+                zero_crossings as f32 / (samples.len() - 1).max(1) as f32;
+        }
+
+        let magnitude_sum = bins.iter().sum::<f32>();
+        let power_sum = bins.iter().map(|bin| bin * bin).sum::<f32>();
+        if magnitude_sum <= 0.0 || bins.is_empty() {
+            return;
+        }
+
+        features[SignalFeature::SpectralCentroid as usize] = bins
+            .iter()
+            .enumerate()
+            .map(|(index, bin)| index as f32 * fft_resolution * bin)
+            .sum::<f32>()
+            / magnitude_sum;
+
+        // Geometric mean of the powers over their arithmetic mean
+        let log_power_mean = bins
+            .iter()
+            .map(|bin| (bin * bin + FLATNESS_EPSILON).ln())
+            .sum::<f32>()
+            / bins.len() as f32;
+        features[SignalFeature::SpectralFlatness as usize] =
+            (log_power_mean.exp() / (power_sum / bins.len() as f32)).min(1.0);
+
+        let mut cumulated_power = 0.0;
+        let rolloff_index = bins
+            .iter()
+            .position(|bin| {
+                cumulated_power += bin * bin;
+                cumulated_power >= ROLLOFF_ENERGY_RATIO * power_sum
+            })
+            .unwrap_or(bins.len() - 1);
+        features[SignalFeature::SpectralRolloff as usize] = rolloff_index as f32 * fft_resolution;
+    }
+}
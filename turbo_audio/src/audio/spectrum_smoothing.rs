@@ -0,0 +1,96 @@
+use crate::audio::gain_control::{log_bin_bands, smoothing_factor};
+use serde::{Deserialize, Serialize};
+use turbo_plugin::audio_api::AudioChannel;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SmoothingConfig {
+    /// Time for the smoothed spectrum to decay towards a quieter signal, for log-spaced bands
+    /// from the lowest to the highest frequencies. Rises are never smoothed.
+    pub band_decay_seconds: Vec<f32>,
+    /// Time a peak stays in place before falling.
+    pub peak_hold_seconds: f32,
+    /// Speed at which a peak falls once its hold time elapsed, in normalized amplitude per second.
+    pub peak_fall_rate: f32,
+}
+
+impl Default for SmoothingConfig {
+    fn default() -> Self {
+        Self {
+            band_decay_seconds: vec![0.3, 0.2, 0.15, 0.1],
+            peak_hold_seconds: 0.5,
+            peak_fall_rate: 1.0,
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+struct Peak {
+    value: f32,
+    remaining_hold_seconds: f32,
+}
+
+/// Maintains the smoothed and peak-hold views of the normalized spectrum.
+pub struct SpectrumSmoothing {
+    config: SmoothingConfig,
+    // Decay time constant of every bin
+    bin_decay_seconds: Vec<f32>,
+    peaks: [Vec<Peak>; AudioChannel::COUNT],
+}
+
+impl SpectrumSmoothing {
+    pub fn new(config: SmoothingConfig, bin_count: usize, fft_resolution: f32) -> Self {
+        let band_decay_seconds = if config.band_decay_seconds.is_empty() {
+            SmoothingConfig::default().band_decay_seconds
+        } else {
+            config.band_decay_seconds.clone()
+        };
+        let bin_decay_seconds = log_bin_bands(bin_count, fft_resolution, band_decay_seconds.len())
+            .into_iter()
+            .map(|band| band_decay_seconds[band])
+            .collect();
+        Self {
+            config,
+            bin_decay_seconds,
+            peaks: std::array::from_fn(|_| vec![Peak::default(); bin_count]),
+        }
+    }
+
+    /// Moves `smoothed_bins` and `peak_bins` of `channel` towards `bins`. `elapsed_seconds` is the
+    /// duration since the previous update.
+    pub fn process(
+        &mut self,
+        channel: AudioChannel,
+        bins: &[f32],
+        smoothed_bins: &mut Vec<f32>,
+        peak_bins: &mut Vec<f32>,
+        elapsed_seconds: f32,
+    ) {
+        smoothed_bins.resize(bins.len(), 0.0);
+        for ((smoothed, bin), decay_seconds) in smoothed_bins
+            .iter_mut()
+            .zip(bins.iter())
+            .zip(self.bin_decay_seconds.iter())
+        {
+            if *bin > *smoothed {
+                *smoothed = *bin;
+            } else {
+                *smoothed += (bin - *smoothed) * smoothing_factor(elapsed_seconds, *decay_seconds);
+            }
+        }
+
+        let peaks = &mut self.peaks[channel as usize];
+        for (peak, bin) in peaks.iter_mut().zip(bins.iter()) {
+            if *bin >= peak.value {
+                peak.value = *bin;
+                peak.remaining_hold_seconds = self.config.peak_hold_seconds;
+            } else if peak.remaining_hold_seconds > 0.0 {
+                peak.remaining_hold_seconds -= elapsed_seconds;
+            } else {
+                peak.value = (peak.value - self.config.peak_fall_rate * elapsed_seconds).max(*bin);
+            }
+        }
+        peak_bins.clear();
+        peak_bins.extend(peaks.iter().map(|peak| peak.value));
+    }
+}
@@ -0,0 +1,194 @@
+use crate::audio::beat_detection::BeatResult;
+use std::{collections::VecDeque, time::Instant};
+
+// Rate at which the onset envelope is sampled, independently of the tick rate
+const ENVELOPE_RATE: f32 = 100.0;
+// Length of onset envelope used for the autocorrelation
+const ENVELOPE_SECONDS: f32 = 6.0;
+const MIN_BPM: f32 = 60.0;
+const MAX_BPM: f32 = 200.0;
+// Tempo that the lag weighting is centered on. Helps choosing between half and double tempos
+const PREFERRED_BPM: f32 = 120.0;
+// Width of the lag weighting, in octaves
+const PREFERRED_BPM_OCTAVE_WIDTH: f32 = 1.0;
+// Time constant of the tracked tempo following the autocorrelation estimates
+const BPM_SMOOTHING_SECONDS: f32 = 0.33;
+// How much a detected beat pulls the phase towards 0
+const PHASE_CORRECTION: f32 = 0.2;
+// Taps further apart than this start a new tap sequence
+const MAX_TAP_INTERVAL_SECONDS: f32 = 2.0;
+const TAP_HISTORY_LENGTH: usize = 8;
+// Time after the last tap when the tapped tempo is dropped
+const TAPPED_TEMPO_TIMEOUT_SECONDS: f32 = 60.0;
+
+pub struct TempoTracker {
+    onset_envelope: VecDeque<f32>,
+    // Onset strength accumulated since the last envelope sample
+    pending_onset_strength: f32,
+    // Time since the last envelope sample
+    pending_seconds: f32,
+    estimated_bpm: Option<f32>,
+    fixed_bpm: Option<f32>,
+    // Takes over the fixed and estimated tempos until it times out
+    tapped_bpm: Option<f32>,
+    taps: VecDeque<Instant>,
+    phase: f32,
+}
+
+impl TempoTracker {
+    pub fn new() -> Self {
+        Self {
+            onset_envelope: VecDeque::from(vec![0.0; (ENVELOPE_SECONDS * ENVELOPE_RATE) as usize]),
+            pending_onset_strength: 0.0,
+            pending_seconds: 0.0,
+            estimated_bpm: None,
+            fixed_bpm: None,
+            tapped_bpm: None,
+            taps: VecDeque::with_capacity(TAP_HISTORY_LENGTH),
+            phase: 0.0,
+        }
+    }
+
+    /// Forces the tempo to `bpm`, or goes back to estimating it from the audio when `None`.
+    pub fn set_fixed_bpm(&mut self, bpm: Option<f32>) {
+        self.fixed_bpm = bpm;
+    }
+
+    /// Registers a tap. The tempo follows the taps once at least two of them were close enough,
+    /// and every tap restarts the beat phase. The tapped tempo is dropped a minute after the last
+    /// tap.
+    pub fn tap(&mut self, time: Instant) {
+        if let Some(last_tap) = self.taps.back() {
+            if time.duration_since(*last_tap).as_secs_f32() > MAX_TAP_INTERVAL_SECONDS {
+                self.taps.clear();
+            }
+        }
+        if self.taps.len() == TAP_HISTORY_LENGTH {
+            self.taps.pop_front();
+        }
+        self.taps.push_back(time);
+        self.phase = 0.0;
+
+        if let (Some(first_tap), Some(last_tap)) = (self.taps.front(), self.taps.back()) {
+            if self.taps.len() >= 2 {
+                let mean_interval = last_tap.duration_since(*first_tap).as_secs_f32()
+                    / (self.taps.len() - 1) as f32;
+                let bpm = 60.0 / mean_interval;
+                log::info!("Tapped tempo: {bpm:.1} BPM");
+                self.tapped_bpm = Some(bpm);
+            }
+        }
+    }
+
+    /// `onset_strength` is the spectral flux of the last analysis, `is_beat` whether it found a
+    /// beat and `elapsed_seconds` the duration of audio it covers.
+    pub fn update(
+        &mut self,
+        onset_strength: f32,
+        is_beat: bool,
+        elapsed_seconds: f32,
+        beat_result: &mut BeatResult,
+    ) {
+        self.push_onset_strength(onset_strength, elapsed_seconds);
+
+        if let Some(bpm) = self.estimate_bpm() {
+            let estimated_bpm = self.estimated_bpm.get_or_insert(bpm);
+            let smoothing = 1.0 - (-elapsed_seconds / BPM_SMOOTHING_SECONDS).exp();
+            *estimated_bpm += (bpm - *estimated_bpm) * smoothing;
+        }
+
+        let tap_timed_out = self.taps.back().is_some_and(|last_tap| {
+            last_tap.elapsed().as_secs_f32() > TAPPED_TEMPO_TIMEOUT_SECONDS
+        });
+        if tap_timed_out {
+            self.taps.clear();
+            if self.tapped_bpm.take().is_some() {
+                log::info!("No tap for a while, dropping the tapped tempo");
+            }
+        }
+
+        let tempo_override = self.tapped_bpm.or(self.fixed_bpm);
+        let bpm = tempo_override.or(self.estimated_bpm);
+
+        if let Some(bpm) = bpm {
+            self.phase = (self.phase + elapsed_seconds * bpm / 60.0).fract();
+            // Lock the phase to the detected beats, unless the user is in control of the tempo
+            if is_beat && tempo_override.is_none() {
+                let phase_error = if self.phase > 0.5 {
+                    self.phase - 1.0
+                } else {
+                    self.phase
+                };
+                self.phase = (self.phase - phase_error * PHASE_CORRECTION).rem_euclid(1.0);
+            }
+        }
+
+        beat_result.bpm = bpm.unwrap_or_default();
+        beat_result.beat_phase = self.phase;
+    }
+
+    // Resamples the onset strengths received every tick to the fixed envelope rate
+    fn push_onset_strength(&mut self, onset_strength: f32, elapsed_seconds: f32) {
+        self.pending_onset_strength += onset_strength;
+        self.pending_seconds += elapsed_seconds;
+        while self.pending_seconds >= 1.0 / ENVELOPE_RATE {
+            self.pending_seconds -= 1.0 / ENVELOPE_RATE;
+            self.onset_envelope.pop_front();
+            self.onset_envelope.push_back(self.pending_onset_strength);
+            self.pending_onset_strength = 0.0;
+        }
+    }
+
+    // Finds the lag at which the onset envelope is the most similar to itself
+    fn estimate_bpm(&self) -> Option<f32> {
+        let mean = self.onset_envelope.iter().sum::<f32>() / self.onset_envelope.len() as f32;
+        let envelope: Vec<f32> = self.onset_envelope.iter().map(|x| x - mean).collect();
+        let autocorrelation = |lag: usize| -> f32 {
+            envelope
+                .iter()
+                .zip(envelope.iter().skip(lag))
+                .map(|(a, b)| a * b)
+                .sum::<f32>()
+        };
+
+        let energy = autocorrelation(0);
+        if energy <= f32::EPSILON {
+            return None;
+        }
+
+        let min_lag = (60.0 / MAX_BPM * ENVELOPE_RATE) as usize;
+        let max_lag = (60.0 / MIN_BPM * ENVELOPE_RATE) as usize;
+        let correlations: Vec<f32> = (min_lag - 1..=max_lag + 1).map(autocorrelation).collect();
+
+        let (best_index, _) = correlations[1..correlations.len() - 1]
+            .iter()
+            .enumerate()
+            .map(|(index, correlation)| {
+                let lag = (min_lag + index) as f32;
+                let octaves_from_preferred = (60.0 * ENVELOPE_RATE / lag / PREFERRED_BPM).log2();
+                let weight =
+                    (-0.5 * (octaves_from_preferred / PREFERRED_BPM_OCTAVE_WIDTH).powi(2)).exp();
+                (index + 1, correlation * weight)
+            })
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))?;
+
+        if correlations[best_index] <= 0.0 {
+            return None;
+        }
+
+        // Parabolic interpolation around the peak for a lag more precise than one sample
+        let (previous, peak, next) = (
+            correlations[best_index - 1],
+            correlations[best_index],
+            correlations[best_index + 1],
+        );
+        let denominator = previous - 2.0 * peak + next;
+        let offset = if denominator.abs() > f32::EPSILON {
+            (0.5 * (previous - next) / denominator).clamp(-0.5, 0.5)
+        } else {
+            0.0
+        };
+        let lag = (min_lag + best_index - 1) as f32 + offset;
+        Some(60.0 * ENVELOPE_RATE / lag)
+    }
+}
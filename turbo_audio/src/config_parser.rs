@@ -1,6 +1,11 @@
 use std::path::PathBuf;
 
-use crate::audio::pipewire_listener::StreamConnections;
+use crate::audio::{
+    audio_processing::FftConfig, audio_source::AudioSourceConfig, audio_stream::InputConfig,
+    gain_control::AgcConfig, pipewire_listener::StreamConnections,
+    spectrum_smoothing::SmoothingConfig,
+};
+use crate::plugins::effects::lua::LuaBudgetConfig;
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -26,6 +31,21 @@ pub struct EffectConfig {
     pub effect_id: usize,
     pub settings_id: usize,
     pub effect: EffectConfigType,
+    // Name of the audio input that the effect reads. The main input when unset
+    #[serde(default)]
+    pub audio_input: Option<String>,
+}
+
+// An input analyzed alongside the main one, which is described by the top level fields
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AudioInputConfig {
+    pub name: String,
+    #[serde(default)]
+    pub audio_source: AudioSourceConfig,
+    pub device_name: Option<String>,
+    pub sample_rate: u32,
+    #[serde(default)]
+    pub input: InputConfig,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -58,8 +78,24 @@ pub struct DeviceConfig {
 #[derive(Debug, Serialize, Deserialize)]
 pub struct TurboAudioConfig {
     pub lua_effects_folder: PathBuf,
+    #[serde(default)]
+    pub audio_source: AudioSourceConfig,
     pub device_name: Option<String>,
     pub sample_rate: u32,
+    #[serde(default)]
+    pub input: InputConfig,
+    #[serde(default)]
+    pub audio_inputs: Vec<AudioInputConfig>,
+    // Tempo to use instead of the one estimated from the audio
+    pub fixed_bpm: Option<f32>,
+    #[serde(default)]
+    pub fft: FftConfig,
+    #[serde(default)]
+    pub agc: AgcConfig,
+    #[serde(default)]
+    pub smoothing: SmoothingConfig,
+    #[serde(default)]
+    pub lua_budget: LuaBudgetConfig,
     pub stream_connections: Vec<StreamConnections>,
     pub effect_settings: Vec<EffectSettingConfig>,
     pub effects: Vec<EffectConfig>,
@@ -1,7 +1,10 @@
 use crate::{
-    audio::audio_processing::AudioSignalProcessor,
+    audio::audio_processing::AnalysisResults,
     hot_reloader::{HotReloader, WatchablePath},
-    plugins::effects::{lua::LuaEffectsManager, native::NativeEffectsManager},
+    plugins::effects::{
+        lua::{LuaBudgetConfig, LuaEffectsManager},
+        native::NativeEffectsManager,
+    },
     resources::ledstrip::LedStrip,
     Connection, Effect, EffectSettings,
 };
@@ -9,6 +12,7 @@ use std::{
     collections::HashMap,
     path::{Path, PathBuf},
 };
+use turbo_plugin::effect_plugin::FrameTime;
 
 #[allow(unused)]
 pub struct Controller {
@@ -44,7 +48,7 @@ impl Drop for Controller {
 }
 
 impl Controller {
-    pub fn new(audio_processor: &AudioSignalProcessor, lua_package_root: impl AsRef<Path>) -> Self {
+    pub fn new(lua_package_root: impl AsRef<Path>, lua_budget: LuaBudgetConfig) -> Self {
         let hot_reloader = HotReloader::new(&[
             WatchablePath::recursive(lua_package_root.as_ref()),
             WatchablePath::recursive(PathBuf::from("../effects/bin").as_ref()),
@@ -64,8 +68,8 @@ impl Controller {
             led_strips: Default::default(),
             led_strip_connections: Default::default(),
             effects_registry: Default::default(),
-            native_effect_manager: NativeEffectsManager::new(audio_processor),
-            lua_effects_manager: LuaEffectsManager::new(audio_processor, &lua_package_root),
+            native_effect_manager: NativeEffectsManager::default(),
+            lua_effects_manager: LuaEffectsManager::new(&lua_package_root, lua_budget),
             hot_reloader: hot_reloader.ok(),
         }
     }
@@ -133,13 +137,29 @@ impl Controller {
                     }
                     Effect::Lua(effect) => {
                         self.lua_effects_manager.reload_effect(effect);
+
+                        // The schema may have changed, so the settings are checked again
+                        let settings = self
+                            .effect_settings
+                            .get(&effect_id)
+                            .and_then(|settings_id| self.settings.get(settings_id));
+                        if let Some(EffectSettings::Lua(settings)) = settings {
+                            if let Err(e) = effect.set_settings(settings) {
+                                log::error!("Invalid settings for effect {effect_id}. {e}");
+                            }
+                        }
                     }
                 };
             }
         }
     }
 
-    pub fn add_lua_effect(&mut self, id: usize, effect_path: impl AsRef<Path>) {
+    pub fn add_lua_effect(
+        &mut self,
+        id: usize,
+        effect_path: impl AsRef<Path>,
+        audio_input: AnalysisResults,
+    ) {
         let canonicalized_effect_path = match std::fs::canonicalize(&effect_path) {
             Ok(x) => x,
             Err(e) => {
@@ -150,7 +170,7 @@ impl Controller {
 
         let effect = self
             .lua_effects_manager
-            .create_effect(&canonicalized_effect_path);
+            .create_effect(&canonicalized_effect_path, audio_input);
 
         let effect = match effect {
             Err(e) => {
@@ -166,14 +186,19 @@ impl Controller {
         self.on_effect_add(id, canonicalized_effect_path, effect);
     }
 
-    pub fn add_native_effect(&mut self, id: usize, effect_path: impl AsRef<Path>) {
+    pub fn add_native_effect(
+        &mut self,
+        id: usize,
+        effect_path: impl AsRef<Path>,
+        audio_input: &AnalysisResults,
+    ) {
         let Ok(canonicalized_effect_path) = std::fs::canonicalize(&effect_path) else {
             return;
         };
 
         let effect = self
             .native_effect_manager
-            .create_effect(&canonicalized_effect_path);
+            .create_effect(&canonicalized_effect_path, audio_input);
 
         let effect = match effect {
             Err(e) => {
@@ -205,17 +230,42 @@ impl Controller {
             .push(id);
     }
 
+    /// Adds or replaces the settings `id`. Replaced settings are passed to the lua effects using
+    /// them, which are only notified when their settings actually changed.
     pub fn add_settings(&mut self, id: usize, settings: EffectSettings) {
+        if let EffectSettings::Lua(lua_settings) = &settings {
+            for (effect_id, _) in self
+                .effect_settings
+                .iter()
+                .filter(|(_, settings_id)| **settings_id == id)
+            {
+                let effect = self.effects.as_mut().unwrap().get_mut(effect_id);
+                if let Some(Effect::Lua(effect)) = effect {
+                    if let Err(e) = effect.set_settings(lua_settings) {
+                        log::error!("Invalid settings {id} for effect {effect_id}. {e}");
+                    }
+                }
+            }
+        }
         self.settings.insert(id, settings);
     }
 
+    /// Returns false if the settings don't exist. Settings that don't match the schema of a lua
+    /// effect are still linked, but the effect isn't ticked until they are fixed.
     pub fn link_effect_to_settings(&mut self, effect_id: usize, settings_id: usize) -> bool {
-        if self.settings.contains_key(&settings_id) {
-            self.effect_settings.insert(effect_id, settings_id);
-            true
-        } else {
-            false
+        let Some(settings) = self.settings.get(&settings_id) else {
+            return false;
+        };
+
+        let effect = self.effects.as_mut().unwrap().get_mut(&effect_id);
+        if let (Some(Effect::Lua(effect)), EffectSettings::Lua(settings)) = (effect, settings) {
+            if let Err(e) = effect.set_settings(settings) {
+                log::error!("Invalid settings {settings_id} for effect {effect_id}. {e}");
+            }
         }
+
+        self.effect_settings.insert(effect_id, settings_id);
+        true
     }
 
     pub fn add_connection(&mut self, connection_id: usize, connection: Connection) {
@@ -240,7 +290,11 @@ impl Controller {
         }
     }
 
-    pub fn update_led_strips(&mut self) {
+    pub fn led_strips(&self) -> &HashMap<usize, LedStrip> {
+        &self.led_strips
+    }
+
+    pub fn update_led_strips(&mut self, time: FrameTime) {
         for (led_strip_id, led_strip) in self.led_strips.iter_mut() {
             for (effect_id, interval) in &led_strip.effects {
                 let leds = match led_strip.colors.get_mut(interval.0..=interval.1) {
@@ -272,13 +326,13 @@ impl Controller {
 
                 let setting = self.settings.get(setting_id);
                 match (effect, setting) {
-                    (Effect::Lua(lua), Some(EffectSettings::Lua(settings))) => {
-                        if let Err(e) = lua.tick(leds, settings) {
+                    (Effect::Lua(lua), Some(EffectSettings::Lua(_settings))) => {
+                        if let Err(e) = lua.tick(leds, time) {
                             log::error!("Error when executing lua function: {:?}", e);
                         }
                     }
                     (Effect::Native(native), Some(EffectSettings::Native(_settings))) => {
-                        native.tick(leds).unwrap();
+                        native.tick(leds, time).unwrap();
                     }
                     _ => panic!("Effect doesn't match settings"),
                 }
@@ -0,0 +1,91 @@
+use crate::audio::pipewire_listener::PipewireController;
+use anyhow::Result;
+use cpal::{
+    traits::{DeviceTrait, HostTrait},
+    SupportedBufferSize,
+};
+use std::time::Duration;
+
+// The pipewire registry is filled asynchronously, so give it some time to announce every object
+const PIPEWIRE_DISCOVERY_TIME: Duration = Duration::from_millis(500);
+
+/// Prints the input devices of every audio host with their supported configs, then the pipewire
+/// nodes and their ports. The names are the ones expected by `device_name` and
+/// `stream_connections` in the settings.
+pub fn list_devices() -> Result<()> {
+    let default_host_id = cpal::default_host().id();
+    for host_id in cpal::available_hosts() {
+        let default_marker = if host_id == default_host_id {
+            " (default)"
+        } else {
+            ""
+        };
+        println!("Host {}{default_marker}", host_id.name());
+
+        let host = match cpal::host_from_id(host_id) {
+            Ok(host) => host,
+            Err(e) => {
+                println!("  Unavailable: {e}");
+                continue;
+            }
+        };
+        let default_device_name = host
+            .default_input_device()
+            .and_then(|device| device.name().ok());
+        let devices = match host.input_devices() {
+            Ok(devices) => devices,
+            Err(e) => {
+                println!("  Couldn't list the input devices: {e}");
+                continue;
+            }
+        };
+        for device in devices {
+            let name = device
+                .name()
+                .unwrap_or_else(|e| format!("<unnamed device: {e}>"));
+            let default_marker = if default_device_name.as_ref() == Some(&name) {
+                " (default)"
+            } else {
+                ""
+            };
+            println!("  Input device {name:?}{default_marker}");
+
+            let configs = match device.supported_input_configs() {
+                Ok(configs) => configs,
+                Err(e) => {
+                    println!("    Couldn't get the supported configs: {e}");
+                    continue;
+                }
+            };
+            for config in configs {
+                let buffer_size = match config.buffer_size() {
+                    SupportedBufferSize::Range { min, max } => format!("{min}-{max} frames"),
+                    SupportedBufferSize::Unknown => "unknown buffer size".to_owned(),
+                };
+                println!(
+                    "    {} channels, {}, {}-{} Hz, {buffer_size}",
+                    config.channels(),
+                    config.sample_format(),
+                    config.min_sample_rate().0,
+                    config.max_sample_rate().0
+                );
+            }
+        }
+    }
+
+    println!("Pipewire nodes");
+    let pipewire_controller = PipewireController::new();
+    std::thread::sleep(PIPEWIRE_DISCOVERY_TIME);
+    let mut streams = pipewire_controller.get_streams()?;
+    streams.sort_by(|a, b| a.name.cmp(&b.name));
+    for stream in streams {
+        println!("  {:?}", stream.name);
+        for input in stream.inputs {
+            println!("    Input port {input:?}");
+        }
+        for output in stream.ouputs {
+            println!("    Output port {output:?}");
+        }
+    }
+    Ok(())
+}
@@ -2,24 +2,35 @@ mod audio;
 mod config_parser;
 mod connections;
 mod controller;
+mod devices;
 mod hot_reloader;
 mod plugins;
+mod render;
 mod resources;
 
 use crate::hot_reloader::{HotReloader, WatchablePath};
 use crate::resources::ledstrip::LedStrip;
-use audio::audio_processing::AudioSignalProcessor;
-use audio::{audio_stream::start_audio_loop, pipewire_listener::PipewireController};
-use clap::Parser;
+use audio::{
+    audio_input::{AudioInputResults, AudioInputs},
+    audio_source::AudioSourceConfig,
+    file_source::FileSourceConfig,
+    pipewire_listener::PipewireController,
+};
+use clap::{Parser, Subcommand};
 use config_parser::{ConnectionConfigType, EffectConfigType, SettingsConfigType, TurboAudioConfig};
 use connections::{tcp::TcpConnection, usb::UsbConnection, Connection};
 use controller::Controller;
 use plugins::effects::{
     lua::LuaEffectSettings, native::NativeEffectSettings, Effect, EffectSettings,
 };
+use render::RenderArgs;
+use std::io::BufRead;
 use std::path::PathBuf;
 use std::sync::atomic::{self, AtomicBool};
+use std::sync::mpsc::Receiver;
+use std::time::Instant;
 use std::{fs::File, path::Path};
+use turbo_plugin::effect_plugin::FrameTime;
 
 #[derive(Parser, Debug)]
 #[command(author, version, long_about = None)]
@@ -27,6 +38,29 @@ struct Args {
     /// Settings file
     #[arg(long, default_value_t = String::from("Settings.json"))]
     settings_file: String,
+
+    /// Tap the tempo by pressing enter. It is dropped a minute after the last tap
+    #[arg(long)]
+    tap_tempo: bool,
+
+    /// Play a WAV, FLAC or OGG file instead of the configured audio source
+    #[arg(long)]
+    audio_file: Option<PathBuf>,
+
+    /// Speed at which the audio file is played, relative to real time
+    #[arg(long, default_value_t = 1.0)]
+    playback_speed: f32,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Render the effects against an audio file and write the led frames to a file
+    Render(RenderArgs),
+    /// List the audio input devices and the pipewire nodes, with the names used in the settings
+    Devices,
 }
 
 #[derive(Debug)]
@@ -34,13 +68,22 @@ enum RunLoopError {
     LoadConfigFile,
     StartAudioLoop,
     StartPipewireStream,
+    Render,
+    ListDevices,
 }
 
 pub static SHOULD_QUIT: AtomicBool = AtomicBool::new(false);
 
+// Rate at which the effects are updated and the led strips are sent
+pub const TICKS_PER_SECOND: u32 = 60;
+
+// Returns when asked to quit, or when the config changed in a way that needs a restart
 fn run_loop(
-    mut audio_processor: AudioSignalProcessor,
+    mut audio_inputs: AudioInputs,
     mut controller: Controller,
+    mut config: TurboAudioConfig,
+    load_config: impl Fn() -> anyhow::Result<TurboAudioConfig>,
+    tempo_taps: &Receiver<Instant>,
 ) -> Result<(), RunLoopError> {
     log::info!("Creating watcher on Settings.json");
     let config_hot_reload = HotReloader::new(&[WatchablePath::non_recursive(&PathBuf::from(
@@ -54,14 +97,25 @@ fn run_loop(
     let config_hot_reload = config_hot_reload.ok();
 
     let mut lag = chrono::Duration::zero();
-    let duration_per_tick: chrono::Duration = chrono::Duration::seconds(1) / 60;
+    let duration_per_tick: chrono::Duration =
+        chrono::Duration::seconds(1) / TICKS_PER_SECOND as i32;
     let mut last_loop_start = std::time::Instant::now();
+    let effects_start = Instant::now();
+    let mut last_tick: Option<Instant> = None;
+    let mut frame = 0u64;
     loop {
         if SHOULD_QUIT.load(atomic::Ordering::Relaxed) {
             log::info!("Quitting");
             break Ok(());
         }
 
+        let main_input = &audio_inputs.main;
+        if main_input.source.is_finished() && main_input.processor.pending_sample_count() == 0 {
+            log::info!("Reached the end of the audio file");
+            SHOULD_QUIT.store(true, atomic::Ordering::Relaxed);
+            break Ok(());
+        }
+
         lag = lag
             .checked_add(&chrono::Duration::from_std(last_loop_start.elapsed()).unwrap())
             .unwrap();
@@ -71,17 +125,48 @@ fn run_loop(
             duration_per_tick.checked_sub(&lag).unwrap(),
         );
         std::thread::sleep(current_sleep_duration.to_std().unwrap());
-        audio_processor.compute_fft();
+        for tap in tempo_taps.try_iter() {
+            audio_inputs
+                .iter_mut()
+                .for_each(|input| input.processor.tap_tempo(tap));
+        }
+        audio_inputs
+            .iter_mut()
+            .for_each(|input| input.processor.compute_fft());
 
-        let _fft_result_read_lock = audio_processor.fft_result.read().unwrap();
+        let _fft_result_read_locks: Vec<_> = audio_inputs
+            .iter()
+            .map(|input| input.processor.fft_result.read().unwrap())
+            .collect();
         controller.check_hot_reload();
-        controller.update_led_strips();
+        // The loop can lag behind, so the effects are given the actual time between the ticks
+        let now = Instant::now();
+        let dt = last_tick.map_or(1.0 / TICKS_PER_SECOND as f32, |last_tick| {
+            now.duration_since(last_tick).as_secs_f32()
+        });
+        last_tick = Some(now);
+        controller.update_led_strips(FrameTime {
+            dt,
+            time: now.duration_since(effects_start).as_secs_f64(),
+            frame,
+        });
+        frame += 1;
         controller.send_ledstrip_colors();
 
         if let Some(config_hot_reload) = &config_hot_reload {
             if !config_hot_reload.poll_events().is_empty() {
-                log::info!("Config changed. Restarting.");
-                return Ok(());
+                match load_config() {
+                    Ok(new_config) if only_effect_settings_changed(&config, &new_config) => {
+                        log::info!("Effect settings changed. Applying them.");
+                        add_effect_settings(&mut controller, &new_config);
+                        config = new_config;
+                    }
+                    Ok(_) => {
+                        log::info!("Config changed. Restarting.");
+                        return Ok(());
+                    }
+                    Err(e) => log::error!("Couldn't read the changed config, keeping it: {e:?}"),
+                }
             }
         }
 
@@ -94,24 +179,46 @@ enum LoadControllerError {
     Invalid,
 }
 
-fn load_controller(
-    config: &TurboAudioConfig,
-    audio_processor: &AudioSignalProcessor,
-    lua_effects_foler: impl AsRef<Path>,
-) -> Result<Controller, LoadControllerError> {
-    let mut controller = Controller::new(audio_processor, &lua_effects_foler);
-    for connection_config in config.devices.iter() {
-        match &connection_config.connection {
-            ConnectionConfigType::Tcp(ip) => controller.add_connection(
-                connection_config.id,
-                Connection::Tcp(TcpConnection::new(*ip)),
-            ),
-            ConnectionConfigType::Usb() => {
-                controller.add_connection(connection_config.id, Connection::Usb(UsbConnection {}))
-            }
-        }
+// The settings file, with the audio file given on the command line replacing the audio source
+fn read_config(
+    settings_file: &Path,
+    audio_file: Option<&Path>,
+    playback_speed: f32,
+) -> anyhow::Result<TurboAudioConfig> {
+    let mut config: TurboAudioConfig = serde_json::from_reader(File::open(settings_file)?)?;
+    if let Some(audio_file) = audio_file {
+        config.audio_source = AudioSourceConfig::File(FileSourceConfig {
+            path: audio_file.to_owned(),
+            speed: playback_speed,
+            looping: false,
+        });
     }
+    Ok(config)
+}
+
+// Whether the configs only differ by the values of their effect settings, which are applied to
+// the running effects instead of restarting, so that the lua effects keep their state
+fn only_effect_settings_changed(old: &TurboAudioConfig, new: &TurboAudioConfig) -> bool {
+    let settings_ids = |config: &TurboAudioConfig| {
+        let mut ids: Vec<_> = config
+            .effect_settings
+            .iter()
+            .map(|setting| setting.id)
+            .collect();
+        ids.sort_unstable();
+        ids
+    };
+    let without_effect_settings = |config: &TurboAudioConfig| {
+        serde_json::to_value(config).ok().map(|mut config| {
+            config["effect_settings"] = serde_json::Value::Null;
+            config
+        })
+    };
+    settings_ids(old) == settings_ids(new)
+        && without_effect_settings(old).is_some_and(|old| without_effect_settings(new) == Some(old))
+}
 
+fn add_effect_settings(controller: &mut Controller, config: &TurboAudioConfig) {
     for setting_config in config.effect_settings.iter() {
         match &setting_config.setting {
             SettingsConfigType::Lua(settings) => controller.add_settings(
@@ -126,16 +233,57 @@ fn load_controller(
             ),
         }
     }
+}
+
+// Without `open_connections`, the devices aren't connected to and the led strips aren't sent
+// anywhere
+fn load_controller(
+    config: &TurboAudioConfig,
+    audio_inputs: &AudioInputResults,
+    lua_effects_foler: impl AsRef<Path>,
+    open_connections: bool,
+) -> Result<Controller, LoadControllerError> {
+    let mut controller = Controller::new(&lua_effects_foler, config.lua_budget);
+    let device_configs = if open_connections {
+        config.devices.as_slice()
+    } else {
+        &[]
+    };
+    for connection_config in device_configs {
+        match &connection_config.connection {
+            ConnectionConfigType::Tcp(ip) => controller.add_connection(
+                connection_config.id,
+                Connection::Tcp(TcpConnection::new(*ip)),
+            ),
+            ConnectionConfigType::Usb() => {
+                controller.add_connection(connection_config.id, Connection::Usb(UsbConnection {}))
+            }
+        }
+    }
+
+    add_effect_settings(&mut controller, config);
 
     for effect_settings in config.effects.iter() {
+        let Some(audio_input) = audio_inputs.get(effect_settings.audio_input.as_deref()) else {
+            log::error!(
+                "Effect {} reads the unknown audio input {:?}",
+                effect_settings.effect_id,
+                effect_settings.audio_input
+            );
+            return Err(LoadControllerError::Invalid);
+        };
         match &effect_settings.effect {
             EffectConfigType::Lua(file_name) => {
                 let effect_path = lua_effects_foler.as_ref().to_owned().join(file_name);
-                controller.add_lua_effect(effect_settings.effect_id, effect_path);
+                controller.add_lua_effect(
+                    effect_settings.effect_id,
+                    effect_path,
+                    audio_input.clone(),
+                );
             }
             EffectConfigType::Native(file_name) => {
                 let effect_path = std::path::PathBuf::from(file_name);
-                controller.add_native_effect(effect_settings.effect_id, effect_path);
+                controller.add_native_effect(effect_settings.effect_id, effect_path, audio_input);
             }
         }
         if !controller
@@ -154,8 +302,9 @@ fn load_controller(
             }
         }
         controller.add_led_strip(ledstrip_config.id, ledstrip);
-        if !controller
-            .link_led_strip_to_connection(ledstrip_config.id, ledstrip_config.connection_id)
+        if open_connections
+            && !controller
+                .link_led_strip_to_connection(ledstrip_config.id, ledstrip_config.connection_id)
         {
             return Err(LoadControllerError::Invalid);
         }
@@ -173,18 +322,60 @@ fn main() -> Result<(), RunLoopError> {
     })
     .expect("Couldn't set the CTRL-C handler");
 
-    let Args { settings_file } = Args::parse();
+    let Args {
+        settings_file,
+        tap_tempo,
+        audio_file,
+        playback_speed,
+        command,
+    } = Args::parse();
 
+    match command {
+        Some(Command::Render(render_args)) => {
+            return render::render(&settings_file, render_args).map_err(|e| {
+                log::error!("{:?}", e);
+                RunLoopError::Render
+            });
+        }
+        Some(Command::Devices) => {
+            return devices::list_devices().map_err(|e| {
+                log::error!("{:?}", e);
+                RunLoopError::ListDevices
+            });
+        }
+        None => {}
+    }
+
+    let (tempo_tap_sender, tempo_taps) = std::sync::mpsc::channel();
+    if tap_tempo {
+        log::info!("Press enter to tap the tempo.");
+        std::thread::spawn(move || {
+            for _ in std::io::stdin().lock().lines() {
+                if tempo_tap_sender.send(Instant::now()).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+
+    let load_config = || {
+        read_config(
+            Path::new(&settings_file),
+            audio_file.as_deref(),
+            playback_speed,
+        )
+    };
     loop {
         log::info!("Parsing config.");
-        let config: TurboAudioConfig =
-            serde_json::from_reader(&File::open(settings_file.clone()).unwrap()).unwrap();
+        let config = load_config().map_err(|e| {
+            log::error!("{:?}", e);
+            RunLoopError::LoadConfigFile
+        })?;
         log::info!("Starting audio loop.");
-        let (_stream, audio_rx) = start_audio_loop(config.device_name.clone(), config.sample_rate)
-            .map_err(|e| {
-                log::error!("{:?}", e);
-                RunLoopError::StartAudioLoop
-            })?;
+        let audio_inputs = AudioInputs::start(&config).map_err(|e| {
+            log::error!("{:?}", e);
+            RunLoopError::StartAudioLoop
+        })?;
 
         log::info!("Creating pipewire listener.");
         let pipewire_controller = PipewireController::new();
@@ -196,20 +387,20 @@ fn main() -> Result<(), RunLoopError> {
                 RunLoopError::StartPipewireStream
             })?;
 
-        log::info!("Creating audio processor.");
-        let fft_buffer_size: usize = 1024;
-        let audio_processor =
-            AudioSignalProcessor::new(audio_rx, config.sample_rate, fft_buffer_size);
-
         log::info!("Loading config into controller.");
-        let controller = load_controller(&config, &audio_processor, &config.lua_effects_folder)
-            .map_err(|e| {
-                log::error!("{:?}", e);
-                RunLoopError::LoadConfigFile
-            })?;
+        let controller = load_controller(
+            &config,
+            &audio_inputs.results(),
+            &config.lua_effects_folder,
+            true,
+        )
+        .map_err(|e| {
+            log::error!("{:?}", e);
+            RunLoopError::LoadConfigFile
+        })?;
 
         log::info!("Starting run loop.");
-        run_loop(audio_processor, controller)?;
+        run_loop(audio_inputs, controller, config, load_config, &tempo_taps)?;
         if SHOULD_QUIT.load(atomic::Ordering::Relaxed) {
             log::info!("Quitting");
             break Ok(());
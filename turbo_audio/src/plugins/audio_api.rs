@@ -1,22 +1,40 @@
-use turbo_plugin::audio_api::AudioApi;
+use turbo_plugin::audio_api::{
+    AudioApi, AudioChannel, BandScale, KeyMode, OnsetBand, PitchClass, SignalFeature, SpectrumView,
+};
 
-use crate::audio::audio_processing::FftResult;
+use crate::audio::{
+    audio_processing::FftResult, beat_detection::BeatResult, signal_features::SignalFeatures,
+};
 use std::{
     boxed::Box,
     sync::{Arc, RwLock},
 };
 
-pub fn create_audio_api(fft_result: Arc<RwLock<FftResult>>) -> AudioApi {
+// Everything the audio api functions can read from. Passed to them as their `instance` pointer
+struct AudioApiInstance {
+    fft_result: Arc<RwLock<FftResult>>,
+    beat_result: Arc<RwLock<BeatResult>>,
+    signal_features: Arc<RwLock<SignalFeatures>>,
+}
+
+pub fn create_audio_api(
+    fft_result: Arc<RwLock<FftResult>>,
+    beat_result: Arc<RwLock<BeatResult>>,
+    signal_features: Arc<RwLock<SignalFeatures>>,
+) -> AudioApi {
     extern "C" fn get_average_amplitude(
         instance: *const std::ffi::c_void,
         lower_frequency: std::ffi::c_float,
         upper_frequency: std::ffi::c_float,
+        channel: AudioChannel,
+        view: SpectrumView,
     ) -> std::ffi::c_float {
-        let fft_result = unsafe { &*(instance as *const Arc<RwLock<FftResult>>) };
-        fft_result
+        let instance = unsafe { &*(instance as *const AudioApiInstance) };
+        instance
+            .fft_result
             .read()
             .unwrap()
-            .get_average_amplitude(lower_frequency, upper_frequency)
+            .get_average_amplitude(lower_frequency, upper_frequency, channel, view)
             .unwrap_or_else(|| {
                 log::error!("Invalid frequencies: {lower_frequency} & {upper_frequency}");
                 0.0f32
@@ -26,12 +44,15 @@ pub fn create_audio_api(fft_result: Arc<RwLock<FftResult>>) -> AudioApi {
     extern "C" fn get_frequency_amplitude(
         instance: *const std::ffi::c_void,
         frequency: std::ffi::c_float,
+        channel: AudioChannel,
+        view: SpectrumView,
     ) -> std::ffi::c_float {
-        let fft_result = unsafe { &*(instance as *const Arc<RwLock<FftResult>>) };
-        fft_result
+        let instance = unsafe { &*(instance as *const AudioApiInstance) };
+        instance
+            .fft_result
             .read()
             .unwrap()
-            .get_frequency_amplitude(frequency)
+            .get_frequency_amplitude(frequency, channel, view)
             .unwrap_or_else(|| {
                 log::error!("Invalid frequency: {frequency}");
                 0.0f32
@@ -39,23 +60,154 @@ pub fn create_audio_api(fft_result: Arc<RwLock<FftResult>>) -> AudioApi {
     }
 
     extern "C" fn get_max_frequency(instance: *const std::ffi::c_void) -> std::ffi::c_float {
-        let fft_result = unsafe { &*(instance as *const Arc<RwLock<FftResult>>) };
-        fft_result.read().unwrap().get_max_frequency()
+        let instance = unsafe { &*(instance as *const AudioApiInstance) };
+        instance.fft_result.read().unwrap().get_max_frequency()
+    }
+
+    extern "C" fn get_bands(
+        instance: *const std::ffi::c_void,
+        bands: *mut std::ffi::c_float,
+        band_count: std::ffi::c_ulong,
+        scale: BandScale,
+        channel: AudioChannel,
+        view: SpectrumView,
+    ) {
+        let instance = unsafe { &*(instance as *const AudioApiInstance) };
+        let bands = unsafe { std::slice::from_raw_parts_mut(bands, band_count as _) };
+        instance.fft_result.read().unwrap().with_bands(
+            bands.len(),
+            scale,
+            channel,
+            view,
+            |computed_bands| bands.copy_from_slice(computed_bands),
+        );
+    }
+
+    extern "C" fn is_beat(instance: *const std::ffi::c_void) -> bool {
+        let instance = unsafe { &*(instance as *const AudioApiInstance) };
+        instance.beat_result.read().unwrap().is_beat()
+    }
+
+    extern "C" fn is_onset(instance: *const std::ffi::c_void, band: OnsetBand) -> bool {
+        let instance = unsafe { &*(instance as *const AudioApiInstance) };
+        instance.beat_result.read().unwrap().is_onset(band)
+    }
+
+    extern "C" fn get_beat_phase(instance: *const std::ffi::c_void) -> std::ffi::c_float {
+        let instance = unsafe { &*(instance as *const AudioApiInstance) };
+        instance.beat_result.read().unwrap().get_beat_phase()
+    }
+
+    extern "C" fn get_beat_confidence(instance: *const std::ffi::c_void) -> std::ffi::c_float {
+        let instance = unsafe { &*(instance as *const AudioApiInstance) };
+        instance.beat_result.read().unwrap().get_beat_confidence()
+    }
+
+    extern "C" fn get_bpm(instance: *const std::ffi::c_void) -> std::ffi::c_float {
+        let instance = unsafe { &*(instance as *const AudioApiInstance) };
+        instance.beat_result.read().unwrap().get_bpm()
+    }
+
+    extern "C" fn get_signal_feature(
+        instance: *const std::ffi::c_void,
+        feature: SignalFeature,
+        channel: AudioChannel,
+    ) -> std::ffi::c_float {
+        let instance = unsafe { &*(instance as *const AudioApiInstance) };
+        instance
+            .signal_features
+            .read()
+            .unwrap()
+            .get_feature(feature, channel)
+    }
+
+    extern "C" fn get_waveform(
+        instance: *const std::ffi::c_void,
+        waveform: *mut std::ffi::c_float,
+        point_count: std::ffi::c_ulong,
+        channel: AudioChannel,
+    ) {
+        let instance = unsafe { &*(instance as *const AudioApiInstance) };
+        let waveform = unsafe { std::slice::from_raw_parts_mut(waveform, point_count as _) };
+        instance
+            .signal_features
+            .read()
+            .unwrap()
+            .get_waveform(waveform, channel);
+    }
+
+    extern "C" fn get_chroma(
+        instance: *const std::ffi::c_void,
+        chroma: *mut std::ffi::c_float,
+        channel: AudioChannel,
+    ) {
+        let instance = unsafe { &*(instance as *const AudioApiInstance) };
+        let chroma = unsafe { std::slice::from_raw_parts_mut(chroma, PitchClass::COUNT) };
+        chroma.copy_from_slice(instance.fft_result.read().unwrap().get_chroma(channel));
+    }
+
+    extern "C" fn get_dominant_pitch(
+        instance: *const std::ffi::c_void,
+        channel: AudioChannel,
+        pitch: *mut PitchClass,
+    ) -> bool {
+        let instance = unsafe { &*(instance as *const AudioApiInstance) };
+        let Some(dominant_pitch) = instance
+            .fft_result
+            .read()
+            .unwrap()
+            .get_dominant_pitch(channel)
+        else {
+            return false;
+        };
+        unsafe { *pitch = dominant_pitch };
+        true
+    }
+
+    extern "C" fn get_key(
+        instance: *const std::ffi::c_void,
+        tonic: *mut PitchClass,
+        mode: *mut KeyMode,
+    ) -> bool {
+        let instance = unsafe { &*(instance as *const AudioApiInstance) };
+        let Some((key_tonic, key_mode)) = instance.fft_result.read().unwrap().get_key() else {
+            return false;
+        };
+        unsafe {
+            *tonic = key_tonic;
+            *mode = key_mode;
+        }
+        true
     }
 
     extern "C" fn free(instance: *const std::ffi::c_void) {
         unsafe {
-            drop(Box::from_raw(instance as *mut Arc<RwLock<FftResult>>));
+            drop(Box::from_raw(instance as *mut AudioApiInstance));
         }
     }
 
-    let fft_result = Box::new(fft_result);
+    let instance = Box::new(AudioApiInstance {
+        fft_result,
+        beat_result,
+        signal_features,
+    });
 
     AudioApi::new(
-        Box::into_raw(fft_result) as *const _,
+        Box::into_raw(instance) as *const _,
         get_average_amplitude,
         get_frequency_amplitude,
         get_max_frequency,
+        get_bands,
+        is_beat,
+        is_onset,
+        get_beat_phase,
+        get_beat_confidence,
+        get_bpm,
+        get_signal_feature,
+        get_waveform,
+        get_chroma,
+        get_dominant_pitch,
+        get_key,
         free,
     )
 }
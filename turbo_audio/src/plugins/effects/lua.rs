@@ -1,14 +1,62 @@
 use super::Effect;
-use crate::audio::{audio_processing::AudioSignalProcessor, audio_processing::FftResult};
+use crate::audio::{
+    audio_processing::{AnalysisResults, FftResult},
+    beat_detection::BeatResult,
+    signal_features::SignalFeatures,
+};
 use jsonschema::JSONSchema;
-use mlua::{Error, Function, Lua, LuaSerdeExt, Table, Value};
+use mlua::{
+    ChunkMode, Error, Function, HookTriggers, Lua, LuaOptions, LuaSerdeExt, MultiValue, StdLib,
+    Table, Thread, Value,
+};
+use serde::{Deserialize, Serialize};
 use std::{
-    fs,
-    os::unix::prelude::OsStrExt,
+    cell::Cell,
+    fmt, fs,
     path::{Path, PathBuf},
     sync::{Arc, RwLock},
+    time::{Duration, Instant},
+};
+use turbo_plugin::{
+    audio_api::{AudioChannel, BandScale, KeyMode, OnsetBand, SignalFeature, SpectrumView},
+    effect_plugin::FrameTime,
+    Color,
 };
-use turbo_plugin::Color;
+
+// Registry key of the settings converted to Lua
+const SETTINGS_REGISTRY_KEY: &str = "turbo_audio_settings";
+// Number of instructions between the checks of the tick budget
+const BUDGET_CHECK_INSTRUCTIONS: u32 = 10_000;
+// Base functions that read files or load bytecode, and the ones catching errors, which would let
+// an effect carry on after being aborted for running over its budget
+const REMOVED_BASE_FUNCTIONS: [&str; 6] = [
+    "dofile",
+    "loadfile",
+    "load",
+    "loadstring",
+    "pcall",
+    "xpcall",
+];
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LuaBudgetConfig {
+    /// Longest time a tick of an effect may run before it is aborted. Loading the effect and
+    /// calling its `OnSettingsChanged` get the same budget.
+    pub tick_budget_ms: f32,
+    /// Number of aborted ticks in a row after which the effect is disabled until it is reloaded.
+    /// At least 1.
+    pub max_overruns: u32,
+}
+
+impl Default for LuaBudgetConfig {
+    fn default() -> Self {
+        Self {
+            tick_budget_ms: 10.0,
+            max_overruns: 10,
+        }
+    }
+}
 
 #[derive(Debug)]
 pub enum InvalidEffectError {
@@ -23,35 +71,69 @@ pub enum LuaEffectLoadError {
     Effect(InvalidEffectError),
 }
 
+/// The settings of an effect that don't match its `SettingsSchema`.
+#[derive(Debug)]
+pub struct InvalidSettingsError {
+    /// JSON pointer to the invalid value, and what is wrong with it.
+    pub violations: Vec<(String, String)>,
+}
+
+impl fmt::Display for InvalidSettingsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "The settings don't match the effect's schema:")?;
+        for (path, message) in &self.violations {
+            // The empty pointer is the whole settings object
+            let path = if path.is_empty() { "/" } else { path };
+            write!(f, "\n  {path}: {message}")?;
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug)]
 pub enum LuaEffectRuntimeError {
     Lua(Error),
-    WrongColorsLen,
     MissingTickFunction,
-    MissingFrameworkImport,
+    Overrun,
 }
 
 pub struct LuaEffectsManager {
     package_root: PathBuf,
-    fft_result: Arc<RwLock<FftResult>>,
+    budget: LuaBudgetConfig,
 }
 
 impl LuaEffectsManager {
-    pub fn new(audio_processor: &AudioSignalProcessor, package_root: impl AsRef<Path>) -> Self {
+    pub fn new(package_root: impl AsRef<Path>, budget: LuaBudgetConfig) -> Self {
+        let budget = if budget.max_overruns == 0 {
+            let default_max_overruns = LuaBudgetConfig::default().max_overruns;
+            log::warn!(
+                "Ignoring max_overruns of 0, which would disable every lua effect. Using \
+                 {default_max_overruns} instead."
+            );
+            LuaBudgetConfig {
+                max_overruns: default_max_overruns,
+                ..budget
+            }
+        } else {
+            budget
+        };
         Self {
             package_root: package_root.as_ref().to_owned(),
-            fft_result: audio_processor.fft_result.clone(),
+            budget,
         }
     }
 
+    /// Loads the effect, reading the audio analyzed by `audio_input`.
     pub fn create_effect(
         &mut self,
         effect_path: impl AsRef<Path>,
+        audio_input: AnalysisResults,
     ) -> Result<Effect, LuaEffectLoadError> {
         let effect = Effect::Lua(LuaEffect::new(
             &effect_path,
             &self.package_root,
-            self.fft_result.clone(),
+            audio_input,
+            self.budget,
         )?);
         Ok(effect)
     }
@@ -62,7 +144,8 @@ impl LuaEffectsManager {
         let Ok(new_effect) = LuaEffect::new(
             &effect_to_reload.path,
             &self.package_root,
-            self.fft_result.clone(),
+            effect_to_reload.audio_input.clone(),
+            self.budget,
         ) else {
             log::error!("cringe");
             return;
@@ -76,9 +159,16 @@ impl LuaEffectsManager {
 #[derive(Debug)]
 pub struct LuaEffect {
     path: PathBuf,
+    audio_input: AnalysisResults,
     lua: Lua,
-    json_schema: String,
+    // Boxed, since it is only read when the settings change
+    json_schema: Box<serde_json::Value>,
     compiled_json_schema: JSONSchema,
+    // Settings with the schema defaults filled in. None while they are invalid
+    settings: Option<serde_json::Value>,
+    budget: LuaBudgetConfig,
+    // Ticks aborted in a row
+    overruns: u32,
 }
 
 #[derive(Clone, Debug)]
@@ -90,16 +180,39 @@ struct LuaFftResult {
     fft_result: Arc<RwLock<FftResult>>,
 }
 
+// Lua effects pass the channel as an optional string. Omitting it reads the mid channel.
+fn parse_audio_channel(channel: Option<String>) -> mlua::Result<AudioChannel> {
+    channel.map_or(Ok(AudioChannel::default()), |channel| {
+        channel.parse().map_err(Error::RuntimeError)
+    })
+}
+
+// Same for the spectrum view, which defaults to the raw spectrum
+fn parse_spectrum_view(view: Option<String>) -> mlua::Result<SpectrumView> {
+    view.map_or(Ok(SpectrumView::default()), |view| {
+        view.parse().map_err(Error::RuntimeError)
+    })
+}
+
 impl mlua::UserData for LuaFftResult {
     fn add_methods<'lua, M: mlua::UserDataMethods<'lua, Self>>(methods: &mut M) {
         methods.add_method(
             "get_average_amplitude",
-            |_, this, (lower_frequency, upper_frequency): (f32, f32)| {
+            |_,
+             this,
+             (lower_frequency, upper_frequency, channel, view): (
+                f32,
+                f32,
+                Option<String>,
+                Option<String>,
+            )| {
+                let channel = parse_audio_channel(channel)?;
+                let view = parse_spectrum_view(view)?;
                 let result = this
                     .fft_result
                     .read()
                     .unwrap()
-                    .get_average_amplitude(lower_frequency, upper_frequency)
+                    .get_average_amplitude(lower_frequency, upper_frequency, channel, view)
                     .unwrap_or_else(|| {
                         log::error!("Invalid frequencies: {lower_frequency} & {upper_frequency}");
                         0.0f32
@@ -108,22 +221,191 @@ impl mlua::UserData for LuaFftResult {
             },
         );
 
-        methods.add_method("get_frequency_amplitude", |_, this, frequency: f32| {
-            let result = this
+        methods.add_method(
+            "get_frequency_amplitude",
+            |_, this, (frequency, channel, view): (f32, Option<String>, Option<String>)| {
+                let channel = parse_audio_channel(channel)?;
+                let view = parse_spectrum_view(view)?;
+                let result = this
+                    .fft_result
+                    .read()
+                    .unwrap()
+                    .get_frequency_amplitude(frequency, channel, view)
+                    .unwrap_or_else(|| {
+                        log::error!("Invalid frequency: {frequency}");
+                        0.0f32
+                    });
+                Ok(result)
+            },
+        );
+
+        methods.add_method("get_max_frequency", |_, this, _: ()| {
+            Ok(this.fft_result.read().unwrap().get_max_frequency())
+        });
+
+        methods.add_method(
+            "get_bands",
+            |_,
+             this,
+             (band_count, scale, channel, view): (
+                usize,
+                Option<String>,
+                Option<String>,
+                Option<String>,
+            )| {
+                let scale = scale.map_or(Ok(BandScale::default()), |scale| {
+                    scale.parse().map_err(Error::RuntimeError)
+                })?;
+                let channel = parse_audio_channel(channel)?;
+                let view = parse_spectrum_view(view)?;
+                Ok(this.fft_result.read().unwrap().with_bands(
+                    band_count,
+                    scale,
+                    channel,
+                    view,
+                    |bands| bands.to_vec(),
+                ))
+            },
+        );
+
+        methods.add_method("get_chroma", |_, this, channel: Option<String>| {
+            let channel = parse_audio_channel(channel)?;
+            Ok(this.fft_result.read().unwrap().get_chroma(channel).to_vec())
+        });
+
+        // Pitch classes are numbered from 0 (C) to 11 (B)
+        methods.add_method("get_dominant_pitch", |_, this, channel: Option<String>| {
+            let channel = parse_audio_channel(channel)?;
+            Ok(this
                 .fft_result
                 .read()
                 .unwrap()
-                .get_frequency_amplitude(frequency)
-                .unwrap_or_else(|| {
-                    log::error!("Invalid frequency: {frequency}");
-                    0.0f32
-                });
-            Ok(result)
+                .get_dominant_pitch(channel)
+                .map(|pitch| pitch as usize))
         });
 
-        methods.add_method("get_max_frequency", |_, this, _: ()| {
-            Ok(this.fft_result.read().unwrap().get_max_frequency())
+        // Returns the tonic and "major" or "minor", or nil when no key was found yet
+        methods.add_method("get_key", |_, this, _: ()| {
+            let (tonic, mode) = this
+                .fft_result
+                .read()
+                .unwrap()
+                .get_key()
+                .map(|(tonic, mode)| {
+                    let mode = match mode {
+                        KeyMode::Major => "major",
+                        KeyMode::Minor => "minor",
+                    };
+                    (tonic as usize, mode)
+                })
+                .unzip();
+            Ok((tonic, mode))
+        });
+    }
+}
+
+/// The leds of the effect, numbered from 1 like Lua arrays. Colors are integers from 0 to 255.
+struct LuaLeds<'a> {
+    leds: &'a mut [Color],
+}
+
+impl LuaLeds<'_> {
+    fn get_mut(&mut self, index: usize) -> mlua::Result<&mut Color> {
+        let len = self.leds.len();
+        index
+            .checked_sub(1)
+            .and_then(|index| self.leds.get_mut(index))
+            .ok_or_else(|| Error::RuntimeError(format!("Led {index} is out of range (1 to {len})")))
+    }
+}
+
+// Fractions are dropped and out of range values clamped, so effects can pass computed floats
+fn to_color(r: f32, g: f32, b: f32) -> Color {
+    Color {
+        r: r as u8,
+        g: g as u8,
+        b: b as u8,
+    }
+}
+
+impl mlua::UserData for LuaLeds<'_> {
+    fn add_methods<'lua, M: mlua::UserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method_mut(
+            "set",
+            |_, this, (index, r, g, b): (usize, f32, f32, f32)| {
+                *this.get_mut(index)? = to_color(r, g, b);
+                Ok(())
+            },
+        );
+
+        methods.add_method_mut("fill", |_, this, (r, g, b): (f32, f32, f32)| {
+            this.leds.fill(to_color(r, g, b));
+            Ok(())
+        });
+
+        methods.add_method_mut("get", |_, this, index: usize| {
+            let color = *this.get_mut(index)?;
+            Ok((color.r, color.g, color.b))
         });
+
+        methods.add_method("len", |_, this, _: ()| Ok(this.leds.len()));
+        methods.add_meta_method(mlua::MetaMethod::Len, |_, this, _: ()| Ok(this.leds.len()));
+    }
+}
+
+struct LuaAudio {
+    beat_result: Arc<RwLock<BeatResult>>,
+    signal_features: Arc<RwLock<SignalFeatures>>,
+}
+
+impl mlua::UserData for LuaAudio {
+    fn add_methods<'lua, M: mlua::UserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method("is_beat", |_, this, _: ()| {
+            Ok(this.beat_result.read().unwrap().is_beat())
+        });
+
+        methods.add_method("is_onset", |_, this, band: String| {
+            let band: OnsetBand = band.parse().map_err(Error::RuntimeError)?;
+            Ok(this.beat_result.read().unwrap().is_onset(band))
+        });
+
+        methods.add_method("beat_phase", |_, this, _: ()| {
+            Ok(this.beat_result.read().unwrap().get_beat_phase())
+        });
+
+        methods.add_method("beat_confidence", |_, this, _: ()| {
+            Ok(this.beat_result.read().unwrap().get_beat_confidence())
+        });
+
+        methods.add_method("bpm", |_, this, _: ()| {
+            Ok(this.beat_result.read().unwrap().get_bpm())
+        });
+
+        methods.add_method(
+            "feature",
+            |_, this, (feature, channel): (String, Option<String>)| {
+                let feature: SignalFeature = feature.parse().map_err(Error::RuntimeError)?;
+                let channel = parse_audio_channel(channel)?;
+                Ok(this
+                    .signal_features
+                    .read()
+                    .unwrap()
+                    .get_feature(feature, channel))
+            },
+        );
+
+        methods.add_method(
+            "waveform",
+            |_, this, (point_count, channel): (usize, Option<String>)| {
+                let channel = parse_audio_channel(channel)?;
+                let mut waveform = vec![0.0f32; point_count];
+                this.signal_features
+                    .read()
+                    .unwrap()
+                    .get_waveform(&mut waveform, channel);
+                Ok(waveform)
+            },
+        );
     }
 }
 
@@ -131,120 +413,282 @@ impl LuaEffect {
     fn new(
         effect_path: impl AsRef<Path>,
         package_root: impl AsRef<Path>,
-        fft_result: Arc<RwLock<FftResult>>,
+        audio_input: AnalysisResults,
+        budget: LuaBudgetConfig,
     ) -> Result<Self, LuaEffectLoadError> {
         log::info!("Loading lua effect: {}", effect_path.as_ref().display());
         let (lua, json_schema, compiled_json_schema) =
-            Self::load_lua_effect(&effect_path, &package_root, fft_result)?;
+            Self::load_lua_effect(&effect_path, &package_root, audio_input.clone(), &budget)?;
         Ok(Self {
             path: effect_path.as_ref().to_path_buf(),
+            audio_input,
             lua,
             json_schema,
             compiled_json_schema,
+            settings: None,
+            budget,
+            overruns: 0,
         })
     }
 
-    pub fn tick(
+    /// Fills in the defaults of the schema and checks the settings against it. The effect isn't
+    /// ticked until it has valid settings. When they changed, they are passed to Lua and its
+    /// `OnSettingsChanged(old, new)` is called if it exists.
+    pub fn set_settings(
         &mut self,
-        leds: &mut [Color],
         settings: &LuaEffectSettings,
-    ) -> Result<(), LuaEffectRuntimeError> {
+    ) -> Result<(), InvalidSettingsError> {
+        let mut resolved = settings.settings.clone();
+        apply_schema_defaults(&self.json_schema, &mut resolved);
+
+        if let Err(errors) = self.compiled_json_schema.validate(&resolved) {
+            let violations = errors
+                .map(|error| (error.instance_path.to_string(), error.to_string()))
+                .collect();
+            self.settings = None;
+            return Err(InvalidSettingsError { violations });
+        }
+
+        if self.settings.as_ref() == Some(&resolved) {
+            return Ok(());
+        }
+        if let Err(e) = self.push_settings(&resolved) {
+            log::error!(
+                "Couldn't pass the new settings to {}: {e}",
+                self.path.display()
+            );
+        }
+        self.settings = Some(resolved);
+        Ok(())
+    }
+
+    // Sets the `settings` global. The converted settings are also kept in the registry, to be
+    // passed as the old settings on the next change
+    fn push_settings(&self, settings: &serde_json::Value) -> mlua::Result<()> {
+        let old_settings: Value = self.lua.named_registry_value(SETTINGS_REGISTRY_KEY)?;
+        let new_settings = self.lua.to_value(settings)?;
         self.lua
-            .globals()
-            .set("settings", self.lua.to_value(&settings.settings).unwrap())
-            .map_err(LuaEffectRuntimeError::Lua)?;
+            .set_named_registry_value(SETTINGS_REGISTRY_KEY, new_settings.clone())?;
+        self.lua.globals().set("settings", new_settings.clone())?;
 
-        let resize_fn: Function = self
+        if let Some(on_settings_changed) = self
             .lua
             .globals()
-            .get("Resize_Colors")
-            .map_err(|_| LuaEffectRuntimeError::MissingFrameworkImport)?;
+            .get::<_, Option<Function>>("OnSettingsChanged")?
+        {
+            with_budget(&self.lua, &self.budget, || {
+                on_settings_changed.call::<_, ()>((old_settings, new_settings))
+            })
+            .0?;
+        }
+        Ok(())
+    }
 
-        resize_fn
-            .call::<_, Value>(leds.len())
-            .map_err(LuaEffectRuntimeError::Lua)?;
+    /// Calls the effect's `Tick(dt, t, frame)`, see `FrameTime`. The tick is aborted when it runs
+    /// over its budget, and the effect is disabled after too many of these in a row.
+    pub fn tick(
+        &mut self,
+        leds: &mut [Color],
+        time: FrameTime,
+    ) -> Result<(), LuaEffectRuntimeError> {
+        if self.settings.is_none() || self.overruns >= self.budget.max_overruns {
+            return Ok(());
+        }
+        let leds_len = leds.len();
 
-        let tick_fn: Function = self
-            .lua
-            .globals()
+        let globals = self.lua.globals();
+        let tick_fn: Function = globals
             .get("Tick")
             .map_err(|_| LuaEffectRuntimeError::MissingTickFunction)?;
+        // Only defined by the effects using libs/framework.lua, which fill the `Colors` table
+        let resize_fn: Option<Function> = globals.get("Resize_Colors").ok();
+        let set_colors_fn: Option<Function> = globals.get("Set_colors").ok();
+
+        // The effect writes straight into the led strip. `Leds` is invalidated when the scope ends
+        let (result, overran) = with_budget(&self.lua, &self.budget, || {
+            self.lua.scope(|scope| {
+                globals.set("Leds", scope.create_nonstatic_userdata(LuaLeds { leds })?)?;
+                if let Some(resize_fn) = &resize_fn {
+                    resize_fn.call::<_, ()>(leds_len)?;
+                }
+                tick_fn.call::<_, ()>((time.dt, time.time, time.frame))?;
+                if let Some(set_colors_fn) = &set_colors_fn {
+                    set_colors_fn.call::<_, ()>(())?;
+                }
+                Ok(())
+            })
+        });
 
-        tick_fn
-            .call::<_, ()>(())
-            .map_err(LuaEffectRuntimeError::Lua)?;
-
-        let set_colors_fn: Function = self
-            .lua
-            .globals()
-            .get("Set_colors")
-            .map_err(|_| LuaEffectRuntimeError::MissingFrameworkImport)?;
-
-        set_colors_fn
-            .call::<_, ()>(())
-            .map_err(LuaEffectRuntimeError::Lua)?;
-
-        let data = self
-            .lua
-            .globals()
-            .get::<_, mlua::String>("Colors_bin")
-            .map_err(LuaEffectRuntimeError::Lua)?;
-        let data = data.as_bytes();
-
-        if leds.len() * 3 != data.len() {
-            return Err(LuaEffectRuntimeError::WrongColorsLen);
+        if overran {
+            self.overruns += 1;
+            if self.overruns >= self.budget.max_overruns {
+                log::error!(
+                    "Disabling the lua effect {} after {} ticks over budget in a row",
+                    self.path.display(),
+                    self.overruns
+                );
+            }
+            return Err(LuaEffectRuntimeError::Overrun);
         }
-
-        leds.copy_from_slice(
-            &data
-                .chunks_exact(3)
-                .map(|s| Color {
-                    r: s[0],
-                    g: s[1],
-                    b: s[2],
-                })
-                .collect::<Vec<_>>(),
-        );
-
-        Ok(())
+        self.overruns = 0;
+        result.map_err(LuaEffectRuntimeError::Lua)
     }
 
     fn load_lua_effect(
         path: impl AsRef<Path>,
         package_path: impl AsRef<Path>,
-        fft_result: Arc<RwLock<FftResult>>,
-    ) -> Result<(Lua, String, JSONSchema), LuaEffectLoadError> {
+        audio_input: AnalysisResults,
+        budget: &LuaBudgetConfig,
+    ) -> Result<(Lua, Box<serde_json::Value>, JSONSchema), LuaEffectLoadError> {
         let lua_src = fs::read_to_string(path).map_err(LuaEffectLoadError::File)?;
-        let lua = Lua::new();
-
-        {
-            // Add our package path to lua's package path so that it can find the libraries
-            let package = lua.globals().get::<_, mlua::Table>("package").unwrap();
-            let path = package.get::<_, mlua::String>("path").unwrap();
-            let mut new_str = Vec::from(path.as_bytes());
-            new_str.extend_from_slice(b";");
-            new_str.extend_from_slice(package_path.as_ref().as_os_str().as_bytes());
-            if new_str.last() != Some(&b'/') {
-                new_str.extend_from_slice(b"/");
-            }
-            new_str.extend_from_slice(b"?.lua");
-            package
-                .set("path", lua.create_string(&new_str).unwrap())
-                .unwrap(); // Append the new search path to package.path
-
-            lua.globals().set("package", package).unwrap(); // Update the package
+        // No io, os, ffi nor debug: the effects can't reach files, processes or C modules
+        let std_libs =
+            StdLib::TABLE | StdLib::STRING | StdLib::MATH | StdLib::BIT | StdLib::PACKAGE;
+        let lua =
+            Lua::new_with(std_libs, LuaOptions::default()).map_err(LuaEffectLoadError::Lua)?;
+        for function in REMOVED_BASE_FUNCTIONS {
+            lua.globals().set(function, Value::Nil).unwrap();
         }
+        Self::disable_jit(&lua).map_err(LuaEffectLoadError::Lua)?;
+        Self::budget_coroutines(&lua).map_err(LuaEffectLoadError::Lua)?;
+
+        Self::sandbox_require(&lua, package_path.as_ref()).map_err(LuaEffectLoadError::Lua)?;
 
-        lua.load(&lua_src).exec().map_err(LuaEffectLoadError::Lua)?;
+        with_budget(&lua, budget, || lua.load(&lua_src).exec())
+            .0
+            .map_err(LuaEffectLoadError::Lua)?;
         let schema = Self::get_lua_schema(&lua)?;
         let compiled_schema = JSONSchema::compile(&schema)
             .map_err(|_| LuaEffectLoadError::Effect(InvalidEffectError::InvalidSchema))?;
 
         lua.globals()
-            .set("Fft_Result", LuaFftResult { fft_result })
+            .set(
+                "Fft_Result",
+                LuaFftResult {
+                    fft_result: audio_input.fft_result,
+                },
+            )
             .unwrap();
 
-        Ok((lua, schema.to_string(), compiled_schema))
+        lua.globals()
+            .set(
+                "Audio",
+                LuaAudio {
+                    beat_result: audio_input.beat_result,
+                    signal_features: audio_input.signal_features,
+                },
+            )
+            .unwrap();
+
+        Ok((lua, Box::new(schema), compiled_schema))
+    }
+
+    // LuaJIT doesn't run the hooks inside compiled traces, so a hot loop could never be aborted
+    // for running over its budget. Opening the `jit` library starts the compiler: turn it off
+    // right away rather than rely on the library never being opened, and hide it from the effect
+    fn disable_jit(lua: &Lua) -> mlua::Result<()> {
+        lua.load_from_std_lib(StdLib::JIT)?;
+        let jit: Table = lua.globals().get("jit")?;
+        jit.get::<_, Function>("off")?.call::<_, ()>(())?;
+        lua.globals().set("jit", Value::Nil)?;
+        lua.globals()
+            .get::<_, Table>("package")?
+            .get::<_, Table>("loaded")?
+            .set("jit", Value::Nil)
+    }
+
+    // LuaJIT always opens `coroutine` with the base library. mlua only runs the hook on the thread
+    // it was set on and drops it on the others, so `resume` moves it to the resumed coroutine and
+    // back. It also catches errors like `pcall`: once the budget ran out, it raises its error again
+    fn budget_coroutines(lua: &Lua) -> mlua::Result<()> {
+        let coroutine: Table = lua.globals().get("coroutine")?;
+        let resume = lua.create_registry_value(coroutine.get::<_, Function>("resume")?)?;
+        let budgeted_resume =
+            lua.create_function(move |lua, (thread, args): (Thread, MultiValue)| {
+                thread.set_hook(budget_hook_triggers(), |lua, _| check_budget(lua));
+                let results = lua
+                    .registry_value::<Function>(&resume)?
+                    .call::<_, MultiValue>((thread, args));
+                lua.current_thread()
+                    .set_hook(budget_hook_triggers(), |lua, _| check_budget(lua));
+                check_budget(lua)?;
+                results
+            })?;
+        coroutine.set("resume", budgeted_resume.clone())?;
+
+        // The native `wrap` resumes without going through `resume`
+        lua.load(
+            r#"
+            local create, resume, error = ...
+            return function(f)
+                local co = create(f)
+                local function results(ok, ...)
+                    if not ok then
+                        error((...), 0)
+                    end
+                    return ...
+                end
+                return function(...)
+                    return results(resume(co, ...))
+                end
+            end
+            "#,
+        )
+        .set_name("=coroutine.wrap")
+        .call::<_, Function>((
+            coroutine.get::<_, Function>("create")?,
+            budgeted_resume,
+            lua.globals().get::<_, Function>("error")?,
+        ))
+        .and_then(|wrap| coroutine.set("wrap", wrap))
+    }
+
+    // Only lets the effects require the lua files of our package. The searchers of `package` would
+    // load any file the effect points `package.path` to, or C libraries, so they are removed from
+    // the table in place: it stays reachable through the environment of `module`
+    fn sandbox_require(lua: &Lua, package_root: &Path) -> mlua::Result<()> {
+        let package: Table = lua.globals().get("package")?;
+        for field in [
+            "loaders",
+            "searchers",
+            "preload",
+            "loadlib",
+            "searchpath",
+            "path",
+            "cpath",
+        ] {
+            package.set(field, Value::Nil)?;
+        }
+
+        let package_root = package_root.to_owned();
+        let require = lua.create_function(move |lua, name: String| {
+            let loaded: Table = lua.named_registry_value("_LOADED")?;
+            let module: Value = loaded.get(name.as_str())?;
+            if !matches!(module, Value::Nil | Value::Boolean(false)) {
+                return Ok(module);
+            }
+
+            let path = module_path(&package_root, &name)
+                .ok_or_else(|| Error::RuntimeError(format!("Invalid module name '{name}'")))?;
+            let source = fs::read(&path).map_err(|e| {
+                Error::RuntimeError(format!(
+                    "Couldn't load module '{name}' from {}: {e}",
+                    path.display()
+                ))
+            })?;
+            let module: Value = lua
+                .load(&source)
+                .set_name(format!("@{}", path.display()))
+                .set_mode(ChunkMode::Text)
+                .call(name.as_str())?;
+            if !module.is_nil() {
+                loaded.set(name.as_str(), module)?;
+            } else if loaded.get::<_, Value>(name.as_str())?.is_nil() {
+                loaded.set(name.as_str(), true)?;
+            }
+            loaded.get(name.as_str())
+        })?;
+        lua.globals().set("require", require)
     }
 
     fn get_lua_schema(lua: &Lua) -> Result<serde_json::Value, LuaEffectLoadError> {
@@ -256,3 +700,213 @@ impl LuaEffect {
         Ok(schema)
     }
 }
+
+// Resolves a module name like `libs.framework` to `<package_root>/libs/framework.lua`. Names that
+// could point outside of the package root, absolute or with `..` components, are rejected
+fn module_path(package_root: &Path, name: &str) -> Option<PathBuf> {
+    let mut path = package_root.to_owned();
+    for component in name.split(['.', '/']) {
+        if component.is_empty() {
+            return None;
+        }
+        path.push(component);
+    }
+    path.set_extension("lua");
+    Some(path)
+}
+
+// Budget of the Lua code running, set by `with_budget`
+struct RunningBudget {
+    deadline: Instant,
+    overran: Cell<bool>,
+}
+
+fn budget_hook_triggers() -> HookTriggers {
+    HookTriggers::new().every_nth_instruction(BUDGET_CHECK_INSTRUCTIONS)
+}
+
+// Fails once the running Lua code is over its budget
+fn check_budget(lua: &Lua) -> mlua::Result<()> {
+    match lua.app_data_ref::<RunningBudget>() {
+        Some(budget) if Instant::now() >= budget.deadline => {
+            budget.overran.set(true);
+            Err(Error::RuntimeError(
+                "The effect ran over its budget".to_owned(),
+            ))
+        }
+        _ => Ok(()),
+    }
+}
+
+// Runs `f`, aborting it once it runs over the tick budget. Also returns whether it was aborted
+fn with_budget<R>(
+    lua: &Lua,
+    budget: &LuaBudgetConfig,
+    f: impl FnOnce() -> mlua::Result<R>,
+) -> (mlua::Result<R>, bool) {
+    lua.set_app_data(RunningBudget {
+        deadline: Instant::now() + Duration::from_secs_f32(budget.tick_budget_ms.max(0.0) / 1000.0),
+        overran: Cell::new(false),
+    });
+    lua.set_hook(budget_hook_triggers(), |lua, _| check_budget(lua));
+    let result = f();
+    lua.remove_hook();
+    let overran = lua
+        .remove_app_data::<RunningBudget>()
+        .is_some_and(|budget| budget.overran.get());
+    (result, overran)
+}
+
+// Inserts the `default` of every property missing from `settings`, recursing into the nested
+// objects
+fn apply_schema_defaults(schema: &serde_json::Value, settings: &mut serde_json::Value) {
+    if settings.is_null() {
+        if let Some(default) = schema.get("default") {
+            *settings = default.clone();
+        }
+    }
+    let (Some(properties), Some(settings)) = (
+        schema.get("properties").and_then(|p| p.as_object()),
+        settings.as_object_mut(),
+    ) else {
+        return;
+    };
+    for (name, property_schema) in properties {
+        match settings.get_mut(name) {
+            Some(value) => apply_schema_defaults(property_schema, value),
+            None => {
+                if let Some(default) = property_schema.get("default") {
+                    settings.insert(name.clone(), default.clone());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Writes `source` to `<temp dir>/<name>.lua` and loads it as an effect with empty settings
+    fn load_effect(name: &str, source: &str, budget: LuaBudgetConfig) -> LuaEffect {
+        let package_root =
+            std::env::temp_dir().join(format!("turbo_audio_lua_tests_{}", std::process::id()));
+        fs::create_dir_all(&package_root).unwrap();
+        let effect_path = package_root.join(format!("{name}.lua"));
+        fs::write(&effect_path, source).unwrap();
+
+        let audio_input = AnalysisResults {
+            fft_result: Default::default(),
+            beat_result: Default::default(),
+            signal_features: Default::default(),
+        };
+        let mut effect = LuaEffect::new(&effect_path, &package_root, audio_input, budget).unwrap();
+        effect
+            .set_settings(&LuaEffectSettings {
+                settings: serde_json::json!({}),
+            })
+            .unwrap();
+        effect
+    }
+
+    fn tick(effect: &mut LuaEffect) -> Result<(), LuaEffectRuntimeError> {
+        effect.tick(&mut [Color::default(); 4], FrameTime::fixed(0, 60))
+    }
+
+    #[test]
+    fn hot_loops_are_aborted_within_the_budget() {
+        let budget = LuaBudgetConfig::default();
+        let mut effect = load_effect(
+            "hot_loop",
+            r#"
+            SettingsSchema = { type = "object" }
+            Tick = function() while true do end end
+            "#,
+            budget,
+        );
+
+        // Past the first tick, the loop would have been compiled if the JIT was on
+        for _ in 0..3 {
+            let start = Instant::now();
+            assert!(matches!(
+                tick(&mut effect),
+                Err(LuaEffectRuntimeError::Overrun)
+            ));
+            // Some slack for the instructions run between two checks
+            assert!(start.elapsed().as_secs_f32() * 1000.0 < budget.tick_budget_ms * 2.0);
+        }
+    }
+
+    #[test]
+    fn coroutines_cannot_catch_the_budget_error() {
+        let budget = LuaBudgetConfig {
+            tick_budget_ms: 10.0,
+            max_overruns: 3,
+        };
+        let mut effect = load_effect(
+            "coroutine_loop",
+            r#"
+            SettingsSchema = { type = "object" }
+            function Tick()
+                while true do coroutine.resume(coroutine.create(function() while true do end end)) end
+            end
+            "#,
+            budget,
+        );
+
+        for _ in 0..budget.max_overruns {
+            assert!(matches!(
+                tick(&mut effect),
+                Err(LuaEffectRuntimeError::Overrun)
+            ));
+        }
+        // Disabled: the tick returns right away without running the effect
+        let start = Instant::now();
+        assert!(tick(&mut effect).is_ok());
+        assert!(start.elapsed().as_secs_f32() * 1000.0 < budget.tick_budget_ms);
+    }
+
+    #[test]
+    fn require_only_loads_the_package_modules() {
+        let effect = load_effect(
+            "require_libs",
+            r#"
+            SettingsSchema = { type = "object" }
+            "#,
+            LuaBudgetConfig::default(),
+        );
+        let libs = effect.path.parent().unwrap().join("libs");
+        fs::create_dir_all(&libs).unwrap();
+        fs::write(
+            libs.join("answer.lua"),
+            "Loads = (Loads or 0) + 1\nreturn 42",
+        )
+        .unwrap();
+
+        let require = |name: &str| {
+            let source = format!("return require({name:?})");
+            with_budget(&effect.lua, &effect.budget, || {
+                effect.lua.load(&source).eval::<Value>()
+            })
+            .0
+        };
+        assert!(matches!(require("libs.answer"), Ok(Value::Integer(42))));
+        assert!(matches!(require("libs.answer"), Ok(Value::Integer(42))));
+        assert_eq!(effect.lua.globals().get::<_, i64>("Loads").unwrap(), 1);
+        for name in [
+            "..require_libs",
+            "libs..answer",
+            "/etc/passwd",
+            "libs.missing",
+        ] {
+            assert!(require(name).is_err(), "{name}");
+        }
+
+        let searchers: Value = effect
+            .lua
+            .load("return getfenv(module).loaders or package.searchers or package.loadlib")
+            .eval()
+            .unwrap();
+        assert!(searchers.is_nil());
+    }
+}
@@ -1,16 +1,16 @@
-use crate::{
-    audio::audio_processing::{AudioSignalProcessor, FftResult},
-    plugins::audio_api::create_audio_api,
-};
+use crate::{audio::audio_processing::AnalysisResults, plugins::audio_api::create_audio_api};
 use libloading::os::unix::{RTLD_LOCAL, RTLD_NOW};
 use std::{
     collections::HashMap,
     io,
     path::{Path, PathBuf},
-    sync::{Arc, RwLock},
+    sync::Arc,
 };
 use thiserror::Error;
-use turbo_plugin::{effect_plugin::NativeEffectPluginVTable, Color};
+use turbo_plugin::{
+    effect_plugin::{FrameTime, NativeEffectPluginVTable},
+    Color,
+};
 
 use super::Effect;
 
@@ -25,9 +25,9 @@ pub enum Error {
 
 pub type Result<T> = std::result::Result<T, Error>;
 
+#[derive(Default)]
 pub struct NativeEffectsManager {
     libraries: HashMap<PathBuf, Arc<Library>>,
-    fft_result: Arc<RwLock<FftResult>>,
 }
 
 #[derive(Debug)]
@@ -50,27 +50,31 @@ impl Drop for Library {
 }
 
 impl NativeEffectsManager {
-    pub fn new(audio_processor: &AudioSignalProcessor) -> Self {
-        Self {
-            libraries: Default::default(),
-            fft_result: audio_processor.fft_result.clone(),
-        }
-    }
-
-    pub fn create_effect(&mut self, effect_path: impl AsRef<Path>) -> Result<Effect> {
+    /// Creates an instance of the effect, reading the audio analyzed by `audio_input`.
+    pub fn create_effect(
+        &mut self,
+        effect_path: impl AsRef<Path>,
+        audio_input: &AnalysisResults,
+    ) -> Result<Effect> {
         let path = std::fs::canonicalize(&effect_path).unwrap();
 
         let library = match self.libraries.entry(path) {
             std::collections::hash_map::Entry::Occupied(occupied) => occupied.into_mut(),
             std::collections::hash_map::Entry::Vacant(vacant) => {
-                let library = Self::load_library(&self.fft_result, vacant.key())?;
+                let library = Self::load_library(vacant.key())?;
                 vacant.insert(Arc::new(library))
             }
         };
 
-        let plugin = unsafe { ((*library.vtable).plugin_create)() };
+        let audio_api = create_audio_api(
+            audio_input.fft_result.clone(),
+            audio_input.beat_result.clone(),
+            audio_input.signal_features.clone(),
+        );
+        let plugin = unsafe { ((*library.vtable).plugin_create)(audio_api) };
         Ok(Effect::Native(NativeEffect {
             path: effect_path.as_ref().to_owned(),
+            audio_input: audio_input.clone(),
             pointer: plugin,
             library: Some(library.clone()),
             is_dropped: false,
@@ -78,10 +82,13 @@ impl NativeEffectsManager {
     }
 
     pub fn on_file_changed(&mut self, path: impl AsRef<Path>) {
-        self.libraries.remove(&path.as_ref().to_owned());
+        let Some(old_library) = self.libraries.remove(&path.as_ref().to_owned()) else {
+            return;
+        };
         log::info!("Reloading library: {}", path.as_ref().display());
 
-        let Ok(library) = Self::load_library(&self.fft_result, path.as_ref()) else {
+        drop(old_library);
+        let Ok(library) = Self::load_library(path.as_ref()) else {
             log::error!("Error");
             return;
         };
@@ -102,14 +109,15 @@ impl NativeEffectsManager {
 
     pub fn reload_effect(&mut self, effect: &mut NativeEffect) {
         log::info!("Reloading native effect");
-        let Ok(Effect::Native(new_effect)) = self.create_effect(&effect.path) else {
+        let audio_input = effect.audio_input.clone();
+        let Ok(Effect::Native(new_effect)) = self.create_effect(&effect.path, &audio_input) else {
             log::error!("Decaliss");
             return;
         };
         let _ = std::mem::replace(effect, new_effect);
     }
 
-    fn load_library(fft_result: &Arc<RwLock<FftResult>>, path: &Path) -> Result<Library> {
+    fn load_library(path: &Path) -> Result<Library> {
         unsafe {
             let library = libloading::os::unix::Library::open(Some(path), RTLD_NOW | RTLD_LOCAL)?;
 
@@ -119,9 +127,7 @@ impl NativeEffectsManager {
             let vtable =
                 vtable_fn() as *const turbo_plugin::effect_plugin::NativeEffectPluginVTable;
 
-            let audio_api = create_audio_api(fft_result.clone());
-
-            ((*vtable).load)(audio_api);
+            ((*vtable).load)();
 
             Ok(Library {
                 library: Some(library.into()),
@@ -137,6 +143,7 @@ pub struct NativeEffectSettings {}
 #[derive(Debug)]
 pub struct NativeEffect {
     path: PathBuf,
+    audio_input: AnalysisResults,
     pointer: *mut std::ffi::c_void,
     library: Option<Arc<Library>>,
     is_dropped: bool,
@@ -160,10 +167,10 @@ impl Drop for NativeEffect {
 }
 
 impl NativeEffect {
-    pub fn tick(&mut self, leds: &mut [Color]) -> Result<()> {
+    pub fn tick(&mut self, leds: &mut [Color], time: FrameTime) -> Result<()> {
         if let Some(library) = &self.library {
             unsafe {
-                ((*library.vtable).tick)(self.pointer, leds.as_mut_ptr(), leds.len() as _);
+                ((*library.vtable).tick)(self.pointer, leds.as_mut_ptr(), leds.len() as _, time);
             }
         }
         Ok(())
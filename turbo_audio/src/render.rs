@@ -0,0 +1,293 @@
+use crate::{
+    audio::{
+        audio_input::{AudioInput, AudioInputResults},
+        audio_source::AudioSourceConfig,
+        file_source::FileSourceConfig,
+    },
+    config_parser::TurboAudioConfig,
+    load_controller,
+    resources::ledstrip::LedStrip,
+    SHOULD_QUIT,
+};
+use anyhow::{anyhow, bail, Context, Result};
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::atomic,
+    time::Duration,
+};
+use turbo_plugin::effect_plugin::FrameTime;
+
+// Side of the square drawn for every led in GIF renders
+const GIF_LED_SIZE: u16 = 8;
+// Viewers play the GIF frames shorter than 2 hundredths of a second as if they lasted 10, so
+// faster renders are sampled down to this frame rate
+const GIF_MAX_FPS: u32 = 50;
+// Trades a bit of palette quality for a much faster encoding, from 1 (best) to 30
+const GIF_ENCODING_SPEED: i32 = 10;
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+pub enum RenderFormat {
+    /// The RGB bytes of every frame, one after the other.
+    Raw,
+    /// One row of pixels per frame, from top to bottom.
+    Png,
+    /// One animation frame per frame, with one row of pixels per led strip.
+    Gif,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct RenderArgs {
+    /// WAV, FLAC or OGG file to render the effects against
+    audio_file: PathBuf,
+
+    /// File to write the frames to
+    #[arg(long, short)]
+    output: PathBuf,
+
+    /// Output format. Guessed from the extension of the output file by default
+    #[arg(long)]
+    format: Option<RenderFormat>,
+
+    /// Frames rendered per second of audio
+    #[arg(long, default_value_t = 60)]
+    fps: u32,
+}
+
+/// Plays the audio file as fast as possible through the configured effects, and writes every frame
+/// of the led strips to the output file. Nothing is sent to the devices.
+pub fn render(settings_file: impl AsRef<Path>, args: RenderArgs) -> Result<()> {
+    let format = match args.format {
+        Some(format) => format,
+        None => match args.output.extension().and_then(|e| e.to_str()) {
+            Some("png") => RenderFormat::Png,
+            Some("gif") => RenderFormat::Gif,
+            _ => RenderFormat::Raw,
+        },
+    };
+    if args.fps == 0 {
+        bail!("The frame rate must be positive");
+    }
+
+    let settings_file = settings_file.as_ref();
+    let config: TurboAudioConfig = serde_json::from_reader(
+        &File::open(settings_file)
+            .with_context(|| format!("Couldn't open {}", settings_file.display()))?,
+    )?;
+
+    // Speed 0 makes the decoder wait for us, so that no sample is ever dropped
+    let AudioInput {
+        source: audio_source,
+        processor: mut audio_processor,
+    } = AudioInput::start(
+        &AudioSourceConfig::File(FileSourceConfig {
+            path: args.audio_file,
+            speed: 0.0,
+            looping: false,
+        }),
+        None,
+        config.sample_rate,
+        config.input,
+        &config,
+    )?;
+    let sample_rate = audio_processor.sample_rate();
+    let channel_count = audio_processor.channel_count();
+
+    // There is a single file to render against, so every effect reads it whatever its input
+    let audio_inputs = AudioInputResults::single(
+        audio_processor.results(),
+        config.audio_inputs.iter().map(|input| input.name.clone()),
+    );
+    let mut controller = load_controller(&config, &audio_inputs, &config.lua_effects_folder, false)
+        .map_err(|e| anyhow!("Couldn't load the config: {e:?}"))?;
+    let mut frame_writer =
+        FrameWriter::new(format, &args.output, args.fps, controller.led_strips())?;
+
+    let mut frame_index = 0u64;
+    loop {
+        if SHOULD_QUIT.load(atomic::Ordering::Relaxed) {
+            log::info!("Interrupted");
+            break;
+        }
+
+        // Spread the samples evenly over the frames, even when the frame rate doesn't divide the
+        // sample rate
+        let frame_sample_count = ((frame_index + 1) * sample_rate as u64 / args.fps as u64
+            - frame_index * sample_rate as u64 / args.fps as u64)
+            as usize
+            * channel_count as usize;
+        while audio_processor.pending_sample_count() < frame_sample_count
+            && !audio_source.is_finished()
+        {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        if audio_source.is_finished() && audio_processor.pending_sample_count() == 0 {
+            break;
+        }
+
+        audio_processor.compute_fft_exact(frame_sample_count);
+        // The frames are evenly spaced in the audio, whatever the time it takes to render them
+        controller.update_led_strips(FrameTime::fixed(frame_index, args.fps));
+        frame_writer.write_frame(controller.led_strips())?;
+        frame_index += 1;
+    }
+
+    frame_writer.finish()?;
+    log::info!("Rendered {frame_index} frames to {}", args.output.display());
+    Ok(())
+}
+
+enum FrameWriter {
+    Raw(BufWriter<File>),
+    Png {
+        output: BufWriter<File>,
+        width: u32,
+        pixels: Vec<u8>,
+    },
+    Gif {
+        encoder: gif::Encoder<BufWriter<File>>,
+        width: u16,
+        height: u16,
+        fps: u32,
+        gif_fps: u32,
+        rendered_frame_count: u64,
+        frame_index: u64,
+    },
+}
+
+impl FrameWriter {
+    fn new(
+        format: RenderFormat,
+        path: &Path,
+        fps: u32,
+        led_strips: &HashMap<usize, LedStrip>,
+    ) -> Result<Self> {
+        let output = BufWriter::new(
+            File::create(path).with_context(|| format!("Couldn't create {}", path.display()))?,
+        );
+        Ok(match format {
+            RenderFormat::Raw => Self::Raw(output),
+            RenderFormat::Png => Self::Png {
+                output,
+                width: led_strips.values().map(|strip| strip.size).sum::<usize>() as u32,
+                pixels: vec![],
+            },
+            RenderFormat::Gif => {
+                let max_strip_size = led_strips.values().map(|strip| strip.size).max();
+                let width = u16::try_from(max_strip_size.unwrap_or_default())
+                    .ok()
+                    .and_then(|width| width.checked_mul(GIF_LED_SIZE))
+                    .context("The led strips are too long for a GIF")?;
+                let height = u16::try_from(led_strips.len())
+                    .ok()
+                    .and_then(|height| height.checked_mul(GIF_LED_SIZE))
+                    .context("There are too many led strips for a GIF")?;
+                let mut encoder = gif::Encoder::new(output, width, height, &[])?;
+                encoder.set_repeat(gif::Repeat::Infinite)?;
+                if fps > GIF_MAX_FPS {
+                    log::warn!(
+                        "GIFs can't play more than {GIF_MAX_FPS} frames per second. Only writing \
+                         {GIF_MAX_FPS} of the {fps} frames rendered every second"
+                    );
+                }
+                Self::Gif {
+                    encoder,
+                    width,
+                    height,
+                    fps,
+                    gif_fps: fps.min(GIF_MAX_FPS),
+                    rendered_frame_count: 0,
+                    frame_index: 0,
+                }
+            }
+        })
+    }
+
+    fn write_frame(&mut self, led_strips: &HashMap<usize, LedStrip>) -> Result<()> {
+        // Always write the strips in the same order so that renders can be compared
+        let mut led_strips: Vec<_> = led_strips.iter().collect();
+        led_strips.sort_by_key(|(id, _)| **id);
+
+        match self {
+            Self::Raw(output) => {
+                for (_, strip) in led_strips {
+                    output.write_all(bytemuck::cast_slice(strip.colors.as_slice()))?;
+                }
+            }
+            Self::Png { pixels, .. } => {
+                for (_, strip) in led_strips {
+                    pixels.extend_from_slice(bytemuck::cast_slice(strip.colors.as_slice()));
+                }
+            }
+            Self::Gif {
+                encoder,
+                width,
+                height,
+                fps,
+                gif_fps,
+                rendered_frame_count,
+                frame_index,
+            } => {
+                // Only the first rendered frame of every GIF frame is written
+                let gif_frame_index = *rendered_frame_count * *gif_fps as u64 / *fps as u64;
+                *rendered_frame_count += 1;
+                if gif_frame_index < *frame_index {
+                    return Ok(());
+                }
+
+                let mut pixels = vec![0u8; *width as usize * *height as usize * 3];
+                for (row, (_, strip)) in led_strips.iter().enumerate() {
+                    for (column, color) in strip.colors.iter().enumerate() {
+                        for y in 0..GIF_LED_SIZE as usize {
+                            let line_start = (row * GIF_LED_SIZE as usize + y) * *width as usize;
+                            for x in 0..GIF_LED_SIZE as usize {
+                                let index = (line_start + column * GIF_LED_SIZE as usize + x) * 3;
+                                pixels[index..index + 3]
+                                    .copy_from_slice(&[color.r, color.g, color.b]);
+                            }
+                        }
+                    }
+                }
+                let mut frame =
+                    gif::Frame::from_rgb_speed(*width, *height, &pixels, GIF_ENCODING_SPEED);
+                // Delays are in hundredths of a second. Spread the rounding over the frames so
+                // that the animation keeps the frame rate on average. At most 50 frames per
+                // second, no delay is under 2
+                let gif_fps = *gif_fps as u64;
+                frame.delay =
+                    ((*frame_index + 1) * 100 / gif_fps - *frame_index * 100 / gif_fps) as u16;
+                encoder.write_frame(&frame)?;
+                *frame_index += 1;
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<()> {
+        match self {
+            Self::Raw(mut output) => output.flush()?,
+            Self::Png {
+                output,
+                width,
+                pixels,
+            } => {
+                if width == 0 || pixels.is_empty() {
+                    bail!("Nothing was rendered");
+                }
+                let height = (pixels.len() / 3 / width as usize) as u32;
+                let mut encoder = png::Encoder::new(output, width, height);
+                encoder.set_color(png::ColorType::Rgb);
+                encoder.set_depth(png::BitDepth::Eight);
+                let mut writer = encoder.write_header()?;
+                writer.write_image_data(&pixels)?;
+                writer.finish()?;
+            }
+            Self::Gif { encoder, .. } => {
+                encoder.into_inner()?.flush()?;
+            }
+        }
+        Ok(())
+    }
+}
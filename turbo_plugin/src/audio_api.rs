@@ -1,7 +1,225 @@
-use std::{
-    process::abort,
-    sync::{Mutex, OnceLock},
-};
+use std::{cell::Cell, process::abort, str::FromStr};
+
+/// Which view of the input signal an audio query should read from.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+#[repr(C)]
+pub enum AudioChannel {
+    Left,
+    Right,
+    /// (left + right) / 2. Identical to the only channel on mono inputs.
+    #[default]
+    Mid,
+    /// (left - right) / 2. Always silent on mono inputs.
+    Side,
+}
+
+impl AudioChannel {
+    pub const COUNT: usize = 4;
+    pub const ALL: [AudioChannel; Self::COUNT] = [Self::Left, Self::Right, Self::Mid, Self::Side];
+}
+
+impl FromStr for AudioChannel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "left" => Ok(Self::Left),
+            "right" => Ok(Self::Right),
+            "mid" => Ok(Self::Mid),
+            "side" => Ok(Self::Side),
+            _ => Err(format!("Unknown audio channel: {s}")),
+        }
+    }
+}
+
+/// Frequency bands in which onsets are detected independently.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[repr(C)]
+pub enum OnsetBand {
+    /// Kick drums and bass, 20 Hz to 150 Hz. Beats are detected in this band.
+    Low,
+    LowMid,
+    HighMid,
+    /// Hi-hats and cymbals, 4 kHz to 12 kHz.
+    High,
+}
+
+impl OnsetBand {
+    pub const COUNT: usize = 4;
+    pub const ALL: [OnsetBand; Self::COUNT] = [Self::Low, Self::LowMid, Self::HighMid, Self::High];
+}
+
+impl FromStr for OnsetBand {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "low" => Ok(Self::Low),
+            "low_mid" => Ok(Self::LowMid),
+            "high_mid" => Ok(Self::HighMid),
+            "high" => Ok(Self::High),
+            _ => Err(format!("Unknown onset band: {s}")),
+        }
+    }
+}
+
+/// How the frequency range is divided when aggregating the spectrum into bands.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+#[repr(C)]
+pub enum BandScale {
+    Linear,
+    /// Every band covers the same number of octaves.
+    #[default]
+    Log,
+    /// Perceptual pitch scale. Close to linear under 1 kHz and logarithmic above.
+    Mel,
+    /// Perceptual scale following the critical bands of hearing.
+    Bark,
+}
+
+impl FromStr for BandScale {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "linear" => Ok(Self::Linear),
+            "log" => Ok(Self::Log),
+            "mel" => Ok(Self::Mel),
+            "bark" => Ok(Self::Bark),
+            _ => Err(format!("Unknown band scale: {s}")),
+        }
+    }
+}
+
+/// Which processing stage of the spectrum an amplitude query reads from.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+#[repr(C)]
+pub enum SpectrumView {
+    /// The spectrum as computed by the FFT, in the configured scale.
+    #[default]
+    Raw,
+    /// The spectrum after automatic gain control, in [0, 1] whatever the playback volume.
+    Normalized,
+    /// The normalized spectrum, decaying slowly instead of dropping when the signal gets quieter.
+    Smoothed,
+    /// The recent peaks of the normalized spectrum, held for a while before falling.
+    PeakHold,
+}
+
+impl SpectrumView {
+    pub const COUNT: usize = 4;
+    pub const ALL: [SpectrumView; Self::COUNT] =
+        [Self::Raw, Self::Normalized, Self::Smoothed, Self::PeakHold];
+}
+
+impl FromStr for SpectrumView {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "raw" => Ok(Self::Raw),
+            "normalized" => Ok(Self::Normalized),
+            "smoothed" => Ok(Self::Smoothed),
+            "peak_hold" => Ok(Self::PeakHold),
+            _ => Err(format!("Unknown spectrum view: {s}")),
+        }
+    }
+}
+
+/// Scalar descriptors of the latest analysis window.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[repr(C)]
+pub enum SignalFeature {
+    /// Root mean square of the samples. A full scale sine wave has an RMS of 0.707.
+    Rms,
+    /// Largest absolute sample value.
+    Peak,
+    /// Peak divided by RMS. High for percussive sounds, 1.414 for a sine wave.
+    CrestFactor,
+    /// Fraction of consecutive samples that change sign, in [0, 1]. High for noisy sounds.
+    ZeroCrossingRate,
+    /// Center of mass of the spectrum, in Hz. Higher for brighter sounds.
+    SpectralCentroid,
+    /// How close the spectrum is to white noise, in [0, 1]. Close to 0 for tonal sounds.
+    SpectralFlatness,
+    /// Frequency under which 85% of the spectral energy lies, in Hz.
+    SpectralRolloff,
+}
+
+impl SignalFeature {
+    pub const COUNT: usize = 7;
+    pub const ALL: [SignalFeature; Self::COUNT] = [
+        Self::Rms,
+        Self::Peak,
+        Self::CrestFactor,
+        Self::ZeroCrossingRate,
+        Self::SpectralCentroid,
+        Self::SpectralFlatness,
+        Self::SpectralRolloff,
+    ];
+}
+
+impl FromStr for SignalFeature {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rms" => Ok(Self::Rms),
+            "peak" => Ok(Self::Peak),
+            "crest_factor" => Ok(Self::CrestFactor),
+            "zero_crossing_rate" => Ok(Self::ZeroCrossingRate),
+            "spectral_centroid" => Ok(Self::SpectralCentroid),
+            "spectral_flatness" => Ok(Self::SpectralFlatness),
+            "spectral_rolloff" => Ok(Self::SpectralRolloff),
+            _ => Err(format!("Unknown signal feature: {s}")),
+        }
+    }
+}
+
+/// The 12 notes of the chromatic scale, regardless of their octave.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[repr(C)]
+pub enum PitchClass {
+    #[default]
+    C,
+    CSharp,
+    D,
+    DSharp,
+    E,
+    F,
+    FSharp,
+    G,
+    GSharp,
+    A,
+    ASharp,
+    B,
+}
+
+impl PitchClass {
+    pub const COUNT: usize = 12;
+    pub const ALL: [PitchClass; Self::COUNT] = [
+        Self::C,
+        Self::CSharp,
+        Self::D,
+        Self::DSharp,
+        Self::E,
+        Self::F,
+        Self::FSharp,
+        Self::G,
+        Self::GSharp,
+        Self::A,
+        Self::ASharp,
+        Self::B,
+    ];
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[repr(C)]
+pub enum KeyMode {
+    #[default]
+    Major,
+    Minor,
+}
 
 #[derive(Copy, Clone)]
 #[repr(C)]
@@ -11,10 +229,41 @@ pub struct AudioApi {
         *const std::ffi::c_void,
         std::ffi::c_float,
         std::ffi::c_float,
+        AudioChannel,
+        SpectrumView,
+    ) -> std::ffi::c_float,
+    get_frequency_amplitude: extern "C" fn(
+        *const std::ffi::c_void,
+        std::ffi::c_float,
+        AudioChannel,
+        SpectrumView,
     ) -> std::ffi::c_float,
-    get_frequency_amplitude:
-        extern "C" fn(*const std::ffi::c_void, std::ffi::c_float) -> std::ffi::c_float,
     get_max_frequency: extern "C" fn(*const std::ffi::c_void) -> std::ffi::c_float,
+    get_bands: extern "C" fn(
+        *const std::ffi::c_void,
+        *mut std::ffi::c_float,
+        std::ffi::c_ulong,
+        BandScale,
+        AudioChannel,
+        SpectrumView,
+    ),
+    is_beat: extern "C" fn(*const std::ffi::c_void) -> bool,
+    is_onset: extern "C" fn(*const std::ffi::c_void, OnsetBand) -> bool,
+    get_beat_phase: extern "C" fn(*const std::ffi::c_void) -> std::ffi::c_float,
+    get_beat_confidence: extern "C" fn(*const std::ffi::c_void) -> std::ffi::c_float,
+    get_bpm: extern "C" fn(*const std::ffi::c_void) -> std::ffi::c_float,
+    get_signal_feature:
+        extern "C" fn(*const std::ffi::c_void, SignalFeature, AudioChannel) -> std::ffi::c_float,
+    get_waveform: extern "C" fn(
+        *const std::ffi::c_void,
+        *mut std::ffi::c_float,
+        std::ffi::c_ulong,
+        AudioChannel,
+    ),
+    get_chroma: extern "C" fn(*const std::ffi::c_void, *mut std::ffi::c_float, AudioChannel),
+    get_dominant_pitch:
+        extern "C" fn(*const std::ffi::c_void, AudioChannel, *mut PitchClass) -> bool,
+    get_key: extern "C" fn(*const std::ffi::c_void, *mut PitchClass, *mut KeyMode) -> bool,
     free: extern "C" fn(*const std::ffi::c_void),
 }
 
@@ -22,6 +271,7 @@ unsafe impl Send for AudioApi {}
 unsafe impl Sync for AudioApi {}
 
 impl AudioApi {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         instance: *const std::ffi::c_void,
 
@@ -29,12 +279,47 @@ impl AudioApi {
             *const std::ffi::c_void,
             std::ffi::c_float,
             std::ffi::c_float,
+            AudioChannel,
+            SpectrumView,
         ) -> std::ffi::c_float,
         get_frequency_amplitude: extern "C" fn(
             *const std::ffi::c_void,
             std::ffi::c_float,
+            AudioChannel,
+            SpectrumView,
         ) -> std::ffi::c_float,
         get_max_frequency: extern "C" fn(*const std::ffi::c_void) -> std::ffi::c_float,
+        get_bands: extern "C" fn(
+            *const std::ffi::c_void,
+            *mut std::ffi::c_float,
+            std::ffi::c_ulong,
+            BandScale,
+            AudioChannel,
+            SpectrumView,
+        ),
+        is_beat: extern "C" fn(*const std::ffi::c_void) -> bool,
+        is_onset: extern "C" fn(*const std::ffi::c_void, OnsetBand) -> bool,
+        get_beat_phase: extern "C" fn(*const std::ffi::c_void) -> std::ffi::c_float,
+        get_beat_confidence: extern "C" fn(*const std::ffi::c_void) -> std::ffi::c_float,
+        get_bpm: extern "C" fn(*const std::ffi::c_void) -> std::ffi::c_float,
+        get_signal_feature: extern "C" fn(
+            *const std::ffi::c_void,
+            SignalFeature,
+            AudioChannel,
+        ) -> std::ffi::c_float,
+        get_waveform: extern "C" fn(
+            *const std::ffi::c_void,
+            *mut std::ffi::c_float,
+            std::ffi::c_ulong,
+            AudioChannel,
+        ),
+        get_chroma: extern "C" fn(*const std::ffi::c_void, *mut std::ffi::c_float, AudioChannel),
+        get_dominant_pitch: extern "C" fn(
+            *const std::ffi::c_void,
+            AudioChannel,
+            *mut PitchClass,
+        ) -> bool,
+        get_key: extern "C" fn(*const std::ffi::c_void, *mut PitchClass, *mut KeyMode) -> bool,
         free: extern "C" fn(*const std::ffi::c_void),
     ) -> Self {
         Self {
@@ -42,56 +327,156 @@ impl AudioApi {
             get_average_amplitude,
             get_frequency_amplitude,
             get_max_frequency,
+            get_bands,
+            is_beat,
+            is_onset,
+            get_beat_phase,
+            get_beat_confidence,
+            get_bpm,
+            get_signal_feature,
+            get_waveform,
+            get_chroma,
+            get_dominant_pitch,
+            get_key,
             free,
         }
     }
 }
 
-static AUDIO_API_INSTANCE: OnceLock<Mutex<AudioApi>> = OnceLock::new();
+thread_local! {
+    // The audio api of the effect being ticked on this thread
+    static CURRENT_AUDIO_API: Cell<Option<AudioApi>> = const { Cell::new(None) };
+}
 
-pub fn on_load(audio_api: AudioApi) {
-    let mut api = AUDIO_API_INSTANCE
-        .get_or_init(|| Mutex::new(audio_api))
-        .lock()
-        .unwrap();
-    *api = audio_api;
+/// Runs `f` with `audio_api` answering the audio queries made on this thread. Every effect
+/// instance has its own audio api, since each one can read a different audio input.
+pub fn with_audio_api<R>(audio_api: AudioApi, f: impl FnOnce() -> R) -> R {
+    let previous = CURRENT_AUDIO_API.replace(Some(audio_api));
+    let result = f();
+    CURRENT_AUDIO_API.set(previous);
+    result
 }
 
-pub fn get_average_amplitude(lower_freq: f32, upper_freq: f32) -> f32 {
-    let Some(api) = AUDIO_API_INSTANCE.get() else {
-        eprintln!("PLUGIN ERROR: Couldn't get the audio api pointer");
+fn current_audio_api() -> AudioApi {
+    let Some(api) = CURRENT_AUDIO_API.get() else {
+        eprintln!("PLUGIN ERROR: The audio api can only be used while the effect is ticked");
         abort();
     };
-    let api = api.lock().unwrap();
-    (api.get_average_amplitude)(api.instance, lower_freq, upper_freq)
+    api
 }
 
-pub fn get_frequency_amplitude(frequency: f32) -> f32 {
-    let Some(api) = AUDIO_API_INSTANCE.get() else {
-        eprintln!("PLUGIN ERROR: Couldn't get the audio api pointer");
-        abort();
-    };
-    let api = api.lock().unwrap();
+pub fn get_average_amplitude(
+    lower_freq: f32,
+    upper_freq: f32,
+    channel: AudioChannel,
+    view: SpectrumView,
+) -> f32 {
+    let api = current_audio_api();
+    (api.get_average_amplitude)(api.instance, lower_freq, upper_freq, channel, view)
+}
 
-    (api.get_frequency_amplitude)(api.instance, frequency)
+pub fn get_frequency_amplitude(frequency: f32, channel: AudioChannel, view: SpectrumView) -> f32 {
+    let api = current_audio_api();
+
+    (api.get_frequency_amplitude)(api.instance, frequency, channel, view)
 }
 
 pub fn get_max_frequency() -> std::ffi::c_float {
-    let Some(api) = AUDIO_API_INSTANCE.get() else {
-        eprintln!("PLUGIN ERROR: Couldn't get the audio api pointer");
-        abort();
-    };
-    let api = api.lock().unwrap();
+    let api = current_audio_api();
 
     (api.get_max_frequency)(api.instance)
 }
 
-pub fn free() {
-    let Some(api) = AUDIO_API_INSTANCE.get() else {
-        eprintln!("PLUGIN ERROR: Couldn't get the audio api pointer");
-        abort();
-    };
-    let api = api.lock().unwrap();
+/// Fills `bands` with the average amplitude of `bands.len()` bands spread according to `scale`.
+pub fn get_bands(bands: &mut [f32], scale: BandScale, channel: AudioChannel, view: SpectrumView) {
+    let api = current_audio_api();
+
+    (api.get_bands)(
+        api.instance,
+        bands.as_mut_ptr(),
+        bands.len() as _,
+        scale,
+        channel,
+        view,
+    )
+}
+
+pub fn is_beat() -> bool {
+    let api = current_audio_api();
+
+    (api.is_beat)(api.instance)
+}
+
+pub fn is_onset(band: OnsetBand) -> bool {
+    let api = current_audio_api();
+
+    (api.is_onset)(api.instance, band)
+}
+
+pub fn get_beat_phase() -> std::ffi::c_float {
+    let api = current_audio_api();
+
+    (api.get_beat_phase)(api.instance)
+}
+
+pub fn get_beat_confidence() -> std::ffi::c_float {
+    let api = current_audio_api();
+
+    (api.get_beat_confidence)(api.instance)
+}
+
+pub fn get_bpm() -> std::ffi::c_float {
+    let api = current_audio_api();
+
+    (api.get_bpm)(api.instance)
+}
+
+pub fn get_signal_feature(feature: SignalFeature, channel: AudioChannel) -> std::ffi::c_float {
+    let api = current_audio_api();
+
+    (api.get_signal_feature)(api.instance, feature, channel)
+}
+
+/// Fills `waveform` with the latest analysis window of `channel`, resampled to `waveform.len()`
+/// points.
+pub fn get_waveform(waveform: &mut [f32], channel: AudioChannel) {
+    let api = current_audio_api();
+
+    (api.get_waveform)(
+        api.instance,
+        waveform.as_mut_ptr(),
+        waveform.len() as _,
+        channel,
+    )
+}
+
+/// Energy of every pitch class of `channel`, relative to the strongest one.
+pub fn get_chroma(channel: AudioChannel) -> [f32; PitchClass::COUNT] {
+    let api = current_audio_api();
+
+    let mut chroma = [0.0; PitchClass::COUNT];
+    (api.get_chroma)(api.instance, chroma.as_mut_ptr(), channel);
+    chroma
+}
+
+/// The strongest pitch class of `channel`, or `None` on silence.
+pub fn get_dominant_pitch(channel: AudioChannel) -> Option<PitchClass> {
+    let api = current_audio_api();
+
+    let mut pitch = PitchClass::default();
+    (api.get_dominant_pitch)(api.instance, channel, &mut pitch).then_some(pitch)
+}
+
+/// The estimated key of the music, or `None` if it wasn't found yet.
+pub fn get_key() -> Option<(PitchClass, KeyMode)> {
+    let api = current_audio_api();
+
+    let mut tonic = PitchClass::default();
+    let mut mode = KeyMode::default();
+    (api.get_key)(api.instance, &mut tonic, &mut mode).then_some((tonic, mode))
+}
 
-    (api.free)(api.instance)
+/// Frees the instance of an audio api. It can't be used afterwards.
+pub fn free(audio_api: AudioApi) {
+    (audio_api.free)(audio_api.instance)
 }
@@ -1,15 +1,39 @@
 use crate::{audio_api, Color};
 use std::any::Any;
 
+/// When a tick happens. Animate from these rather than by counting ticks, since the tick rate
+/// isn't exactly steady.
+#[derive(Default, Clone, Copy, Debug)]
+#[repr(C)]
+pub struct FrameTime {
+    /// Seconds since the previous tick.
+    pub dt: f32,
+    /// Seconds since the effects started.
+    pub time: f64,
+    /// Index of the tick, starting at 0.
+    pub frame: u64,
+}
+
+impl FrameTime {
+    /// The time of the frame `frame` when ticking exactly `frames_per_second` times per second.
+    pub fn fixed(frame: u64, frames_per_second: u32) -> Self {
+        Self {
+            dt: 1.0 / frames_per_second as f32,
+            time: frame as f64 / frames_per_second as f64,
+            frame,
+        }
+    }
+}
+
 pub trait NativeEffectPlugin: Any + Send + Sync {
     /// Get a name describing the `Plugin`.
     fn name(&self) -> *const std::ffi::c_char;
 
     /// Tick fn
-    fn tick(&self, leds: &mut [Color]);
+    fn tick(&self, leds: &mut [Color], time: FrameTime);
 
     /// A callback called immediately after the plugin is loaded. Usually used
-    /// for initialization.
+    /// for initialization. The audio api can't be used yet, it is only available in `tick`.
     fn load();
 
     /// A callback called immediately before the plugin is unloaded. Use this if
@@ -22,39 +46,52 @@ macro_rules! make_native_effect_plugin {
     ($plugin:ty, $ctor:expr) => {
         #[no_mangle]
         extern "C" fn _plugin_vtable() -> *const std::ffi::c_void {
-            extern "C" fn plugin_create() -> *mut std::ffi::c_void {
-                let plugin = Box::new($ctor);
-                Box::into_raw(plugin) as *mut _
+            // An effect with the audio api of the input it reads
+            struct Instance {
+                plugin: $plugin,
+                audio_api: turbo_plugin::audio_api::AudioApi,
+            }
+
+            extern "C" fn plugin_create(
+                audio_api: turbo_plugin::audio_api::AudioApi,
+            ) -> *mut std::ffi::c_void {
+                let instance = Box::new(Instance {
+                    plugin: $ctor,
+                    audio_api,
+                });
+                Box::into_raw(instance) as *mut _
             }
 
             extern "C" fn plugin_destroy(plugin: *mut std::ffi::c_void) {
-                unsafe {
-                    drop(Box::from_raw(plugin as *mut $plugin));
-                }
+                let instance = unsafe { Box::from_raw(plugin as *mut Instance) };
+                let audio_api = instance.audio_api;
+                drop(instance);
+                turbo_plugin::audio_api::free(audio_api);
             }
 
             extern "C" fn name(plugin: *const std::ffi::c_void) -> *const std::ffi::c_char {
-                let plugin = unsafe { &*(plugin as *const $plugin) };
-                plugin.name()
+                let instance = unsafe { &*(plugin as *const Instance) };
+                instance.plugin.name()
             }
 
             extern "C" fn tick(
                 plugin: *const std::ffi::c_void,
                 colors: *mut Color,
                 len: std::ffi::c_ulong,
+                time: turbo_plugin::effect_plugin::FrameTime,
             ) {
-                let plugin = unsafe { &*(plugin as *const $plugin) };
+                let instance = unsafe { &*(plugin as *const Instance) };
                 let slice = unsafe { std::slice::from_raw_parts_mut(colors, len as _) };
-                plugin.tick(slice);
+                turbo_plugin::audio_api::with_audio_api(instance.audio_api, || {
+                    instance.plugin.tick(slice, time)
+                });
             }
 
-            extern "C" fn load(audio_api: turbo_plugin::audio_api::AudioApi) {
-                turbo_plugin::audio_api::on_load(audio_api);
+            extern "C" fn load() {
                 <$plugin>::load();
             }
 
             extern "C" fn unload() {
-                turbo_plugin::audio_api::free();
                 <$plugin>::unload();
             }
 
@@ -76,8 +113,9 @@ macro_rules! make_native_effect_plugin {
 #[derive(Copy, Clone)]
 #[repr(C)]
 pub struct NativeEffectPluginVTable {
-    /// Function that returns a pointer to a heap allocated plugin
-    pub plugin_create: extern "C" fn() -> *mut std::ffi::c_void,
+    /// Function that returns a pointer to a heap allocated plugin, reading the audio through the
+    /// given audio api. The plugin owns the audio api and frees it when destroyed
+    pub plugin_create: extern "C" fn(audio_api::AudioApi) -> *mut std::ffi::c_void,
 
     /// Function that destroys a heap allocated plugin
     pub plugin_destroy: extern "C" fn(*mut std::ffi::c_void),
@@ -86,11 +124,11 @@ pub struct NativeEffectPluginVTable {
     pub name: extern "C" fn(*const std::ffi::c_void) -> *const std::ffi::c_char,
 
     /// Function that ticks the plugin
-    pub tick: extern "C" fn(*const std::ffi::c_void, *mut Color, std::ffi::c_ulong),
+    pub tick: extern "C" fn(*const std::ffi::c_void, *mut Color, std::ffi::c_ulong, FrameTime),
 
     /// Function that gets called when the shared library gets loaded
     /// Useful for making initialization that is shared between plugin instances
-    pub load: extern "C" fn(audio_api::AudioApi),
+    pub load: extern "C" fn(),
 
     /// Function that gets called when the shared library gets unloaded
     /// Useful for cleaning up anything that was initialized during the `on_load` function
@@ -9,7 +9,7 @@ pub trait NativeGeneralPlugin: Any + Send + Sync {
     fn tick(&self);
 
     /// A callback called immediately after the plugin is loaded. Usually used
-    /// for initialization.
+    /// for initialization. The audio api can't be used yet, it is only available in `tick`.
     fn load();
 
     /// A callback called immediately before the plugin is unloaded. Use this if
@@ -22,20 +22,32 @@ macro_rules! make_general_effect_plugin {
     ($plugin:ty, $ctor:expr) => {
         #[no_mangle]
         extern "C" fn _plugin_vtable() -> *const std::ffi::c_void {
-            extern "C" fn plugin_create() -> *mut std::ffi::c_void {
-                let plugin = Box::new($ctor);
-                Box::into_raw(plugin) as *mut _
+            // A plugin with the audio api of the input it reads
+            struct Instance {
+                plugin: $plugin,
+                audio_api: turbo_plugin::audio_api::AudioApi,
+            }
+
+            extern "C" fn plugin_create(
+                audio_api: turbo_plugin::audio_api::AudioApi,
+            ) -> *mut std::ffi::c_void {
+                let instance = Box::new(Instance {
+                    plugin: $ctor,
+                    audio_api,
+                });
+                Box::into_raw(instance) as *mut _
             }
 
             extern "C" fn plugin_destroy(plugin: *mut std::ffi::c_void) {
-                unsafe {
-                    drop(Box::from_raw(plugin as *mut $plugin));
-                }
+                let instance = unsafe { Box::from_raw(plugin as *mut Instance) };
+                let audio_api = instance.audio_api;
+                drop(instance);
+                turbo_plugin::audio_api::free(audio_api);
             }
 
             extern "C" fn name(plugin: *const std::ffi::c_void) -> *const std::ffi::c_char {
-                let plugin = unsafe { &*(plugin as *const $plugin) };
-                plugin.name()
+                let instance = unsafe { &*(plugin as *const Instance) };
+                instance.plugin.name()
             }
 
             extern "C" fn tick(
@@ -43,13 +55,14 @@ macro_rules! make_general_effect_plugin {
                 colors: *mut Color,
                 len: std::ffi::c_ulong,
             ) {
-                let plugin = unsafe { &*(plugin as *const $plugin) };
+                let instance = unsafe { &*(plugin as *const Instance) };
                 let slice = unsafe { std::slice::from_raw_parts_mut(colors, len as _) };
-                plugin.tick(slice);
+                turbo_plugin::audio_api::with_audio_api(instance.audio_api, || {
+                    instance.plugin.tick(slice)
+                });
             }
 
-            extern "C" fn load(audio_api: turbo_plugin::audio_api::AudioApi) {
-                turbo_plugin::audio_api::on_load(audio_api);
+            extern "C" fn load() {
                 <$plugin>::load();
             }
 
@@ -75,8 +88,9 @@ macro_rules! make_general_effect_plugin {
 #[derive(Copy, Clone)]
 #[repr(C)]
 pub struct NativeGeneralPluginVTable {
-    /// Function that returns a pointer to a heap allocated plugin
-    pub plugin_create: extern "C" fn() -> *mut std::ffi::c_void,
+    /// Function that returns a pointer to a heap allocated plugin, reading the audio through the
+    /// given audio api. The plugin owns the audio api and frees it when destroyed
+    pub plugin_create: extern "C" fn(audio_api::AudioApi) -> *mut std::ffi::c_void,
 
     /// Function that destroys a heap allocated plugin
     pub plugin_destroy: extern "C" fn(*mut std::ffi::c_void),
@@ -89,7 +103,7 @@ pub struct NativeGeneralPluginVTable {
 
     /// Function that gets called when the shared library gets loaded
     /// Useful for making initialization that is shared between plugin instances
-    pub load: extern "C" fn(audio_api::AudioApi),
+    pub load: extern "C" fn(),
 
     /// Function that gets called when the shared library gets unloaded
     /// Useful for cleaning up anything that was initialized during the `on_load` function
@@ -12,4 +12,3 @@ pub struct Color {
     pub g: u8,
     pub b: u8,
 }
-